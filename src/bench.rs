@@ -0,0 +1,166 @@
+//! `primer bench`: runs glxgears and vkcube on a GPU, and on integrated graphics for comparison,
+//! to check that offload actually makes a difference.
+
+use std::io::Read;
+use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
+
+use colored::*;
+
+use crate::{cli, config, device, discrete_gpus, find_gpus, sort_by_rank, Error, GPU};
+
+/// Frames vkcube is asked to draw; the time it needs for them gives the frame rate.
+const VKCUBE_FRAMES: u32 = 3000;
+/// Frames of the short vkcube run whose time is taken off, see `vkcube`.
+const VKCUBE_STARTUP_FRAMES: u32 = 100;
+
+pub fn run(args: &[String], config: config::Config) -> Result<i32, Error> {
+    let options = cli::BenchOptions::parse(args)?;
+    let mut gpus = find_gpus(&device::Udev)?;
+    sort_by_rank(&mut gpus, &config);
+    if let Some(vendor) = &options.vendor {
+        gpus.retain(|gpu| gpu.vendor == *vendor || gpu.integrated);
+    }
+    let target = match options.index {
+        Some(index) => discrete_gpus(&gpus).get(index).copied(),
+        // the GPU a launch would pick, not the one in the lowest slot
+        None => gpus.iter().find(|gpu| !gpu.integrated()),
+    }
+    .ok_or_else(|| Error::NoMatchingGpu(String::from("No discrete GPU to benchmark")))?;
+    let mut targets = vec![target];
+    targets.extend(gpus.iter().find(|gpu| gpu.integrated));
+
+    for gpu in targets {
+        println!(
+            "{}",
+            format!("-- {} --", gpu.name_fancy(&config.colors)).bold()
+        );
+        match glxgears(gpu, &config, options.duration)? {
+            Outcome::Fps(fps) => println!("glxgears: {fps:.1} FPS"),
+            Outcome::Missing => println!("glxgears: not installed, skipped"),
+            Outcome::Failed => {
+                println!("glxgears: no frame rate reported, try a longer --duration")
+            }
+        }
+        match vkcube(gpu, &config, options.duration)? {
+            Outcome::Fps(fps) => println!("vkcube: {fps:.1} FPS"),
+            Outcome::Missing => println!("vkcube: not installed, skipped"),
+            Outcome::Failed => println!(
+                "vkcube: didn't draw {VKCUBE_FRAMES} frames within {}s",
+                options.duration.as_secs_f64()
+            ),
+        }
+    }
+    Ok(0)
+}
+
+enum Outcome {
+    Fps(f64),
+    /// The tool isn't installed.
+    Missing,
+    /// The tool ran but didn't produce a measurement.
+    Failed,
+}
+
+/// Vsync would cap every GPU at the refresh rate.
+fn no_vsync() -> Vec<(String, String)> {
+    vec![
+        (String::from("vblank_mode"), String::from("0")),
+        (String::from("__GL_SYNC_TO_VBLANK"), String::from("0")),
+    ]
+}
+
+/// Average of the `N frames in 5.0 seconds = X FPS` lines glxgears prints every 5 seconds.
+fn glxgears(gpu: &GPU, config: &config::Config, duration: Duration) -> Result<Outcome, Error> {
+    let mut child = match spawn(gpu, config, vec![String::from("glxgears")])? {
+        Some(child) => child,
+        None => return Ok(Outcome::Missing),
+    };
+    std::thread::sleep(duration);
+    let _ = child.kill();
+    let _ = child.wait();
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+    let samples: Vec<f64> = output
+        .lines()
+        .filter_map(|line| line.split_once('=')?.1.trim().strip_suffix("FPS"))
+        .filter_map(|fps| fps.trim().parse().ok())
+        .collect();
+    if samples.is_empty() {
+        return Ok(Outcome::Failed);
+    }
+    Ok(Outcome::Fps(
+        samples.iter().sum::<f64>() / samples.len() as f64,
+    ))
+}
+
+/// Frame rate of vkcube without vsync. vkcube has no frame counter, and the time it needs
+/// for a fixed number of frames also includes starting up and creating a Vulkan instance,
+/// which a dGPU takes longer for. A short run measures that part, and the frame rate comes
+/// from the frames and time the long run takes on top of it.
+fn vkcube(gpu: &GPU, config: &config::Config, duration: Duration) -> Result<Outcome, Error> {
+    let short = match draw_vkcube(gpu, config, VKCUBE_STARTUP_FRAMES, duration)? {
+        Ok(time) => time,
+        Err(outcome) => return Ok(outcome),
+    };
+    let long = match draw_vkcube(gpu, config, VKCUBE_FRAMES, duration)? {
+        Ok(time) => time,
+        Err(outcome) => return Ok(outcome),
+    };
+    match long.checked_sub(short).filter(|extra| !extra.is_zero()) {
+        Some(extra) => Ok(Outcome::Fps(
+            f64::from(VKCUBE_FRAMES - VKCUBE_STARTUP_FRAMES) / extra.as_secs_f64(),
+        )),
+        // the runs were too short to tell apart
+        None => Ok(Outcome::Failed),
+    }
+}
+
+/// How long vkcube takes to start, draw `frames` frames and exit, within `duration`.
+fn draw_vkcube(
+    gpu: &GPU,
+    config: &config::Config,
+    frames: u32,
+    duration: Duration,
+) -> Result<Result<Duration, Outcome>, Error> {
+    let started = Instant::now();
+    let command = ["vkcube", "--present_mode", "0", "--c"]
+        .into_iter()
+        .map(String::from)
+        .chain([frames.to_string()])
+        .collect();
+    let mut child = match spawn(gpu, config, command)? {
+        Some(child) => child,
+        None => return Ok(Err(Outcome::Missing)),
+    };
+    while started.elapsed() < duration {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                return Ok(Err(Outcome::Failed));
+            }
+            return Ok(Ok(started.elapsed()));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(Err(Outcome::Failed))
+}
+
+/// Starts `command` on `gpu` with its output captured, `None` if the tool isn't installed.
+fn spawn(gpu: &GPU, config: &config::Config, command: Vec<String>) -> Result<Option<Child>, Error> {
+    let (program, args) = command.split_first().ok_or(Error::EmptyCommand)?;
+    // the benchmark already named the GPU
+    let spawned = gpu
+        .build_command(program, args, &no_vsync(), config)?
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(child) => Ok(Some(child)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
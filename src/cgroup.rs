@@ -0,0 +1,114 @@
+//! Running commands in their own cgroup for `--cgroup`: a transient systemd scope when a user
+//! systemd instance is around, otherwise a cgroup created directly in cgroupfs.
+
+use std::ffi::CString;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Checks that `name` can be used in a unit and directory name.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "\"{name}\" is not a valid cgroup name, use letters, digits, '-' and '_'"
+        ))
+    }
+}
+
+/// `systemd-run` arguments that run a command in the transient scope `primer-<name>-<pid>`,
+/// `None` without `systemd-run` or a user systemd instance to talk to.
+pub fn systemd_scope(name: &str) -> Option<Vec<String>> {
+    crate::find_in_path("systemd-run")?;
+    let runtime = std::env::var_os("XDG_RUNTIME_DIR")?;
+    if !Path::new(&runtime).join("systemd").exists() {
+        return None;
+    }
+    Some(vec![
+        String::from("systemd-run"),
+        String::from("--user"),
+        String::from("--scope"),
+        String::from("--quiet"),
+        format!("--unit=primer-{name}-{}", std::process::id()),
+        String::from("--"),
+    ])
+}
+
+/// Creates `primer-<name>-<pid>` below primer's own cgroup (cgroup v2 only).
+pub fn create(name: &str) -> Result<PathBuf, String> {
+    let path = crate::proc_root().join("self/cgroup");
+    let own = std::fs::read_to_string(&path)
+        .map_err(|err| format!("can't read {}: {err}", path.display()))?;
+    let own = own
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| String::from("no cgroup v2 hierarchy"))?;
+    let dir = crate::sysfs_root()
+        .join("fs/cgroup")
+        .join(own.trim_start_matches('/'))
+        .join(format!("primer-{name}-{}", std::process::id()));
+    std::fs::create_dir(&dir).map_err(|err| format!("can't create {}: {err}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Controllers `--cgroup` is meant for, so memory and CPU limits can be set on the cgroup.
+const CONTROLLERS: [&str; 2] = ["memory", "cpu"];
+
+/// Enables the memory and CPU controllers for the children of the cgroup `dir` was created
+/// in, which a new cgroup needs before limits can be set on it. The kernel refuses while that
+/// cgroup still has processes of its own, as a session scope usually does.
+pub fn enable_controllers(dir: &Path) -> Result<(), String> {
+    let parent = dir
+        .parent()
+        .ok_or_else(|| String::from("no parent cgroup"))?;
+    let control = parent.join("cgroup.subtree_control");
+    let enabled = std::fs::read_to_string(&control)
+        .map_err(|err| format!("can't read {}: {err}", control.display()))?;
+    let missing: Vec<String> = CONTROLLERS
+        .iter()
+        .filter(|controller| !enabled.split_whitespace().any(|c| c == **controller))
+        .map(|controller| format!("+{controller}"))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    std::fs::write(&control, missing.join(" ")).map_err(|err| {
+        format!(
+            "can't enable {} in {}: {err}",
+            missing.join(" "),
+            control.display()
+        )
+    })
+}
+
+/// Makes the command move itself into the cgroup at `dir` right before it starts.
+pub fn join(cmd: &mut Command, dir: &Path) -> std::io::Result<()> {
+    let procs = CString::new(
+        dir.join("cgroup.procs")
+            .into_os_string()
+            .into_encoded_bytes(),
+    )
+    .map_err(std::io::Error::other)?;
+    // only async-signal-safe calls between fork and exec, so no allocation in here
+    unsafe {
+        cmd.pre_exec(move || {
+            let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // writing 0 moves the writing process
+            let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+            libc::close(fd);
+            if written != 1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,505 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{cgroup, env, log, process::IoPriority, Error, Vendor};
+
+pub const USAGE: &str = "Usage: primer [options] <command>
+       primer run [options] [--] <command>
+       primer config restore
+       primer config show [--format table|json] [options]
+       primer bench [--vendor <vendor>] [--index <n>] [--duration <secs>]
+       primer daemon (built with --features daemon)
+
+Options:
+  --env KEY=VALUE     Set an environment variable for the command (repeatable)
+  --env-file <path>   Load KEY=VALUE lines from a file (repeatable)
+  --append-env KEY    Prepend the value primer sets for KEY to the inherited one instead
+                      of replacing it, joined with ':' (repeatable)
+  --keep-env KEY      Clear the inherited environment except for KEY (repeatable);
+                      primer's variables and --env are still set
+  --drop-env KEY      Don't pass the inherited variable KEY on (repeatable)
+  --capture[=<file>]  Append the command's stdout and stderr to <file>, or to a new
+                      timestamped file in ~/.config/primer/logs
+  --cgroup <name>     Run the command in its own cgroup, a transient systemd scope
+                      named primer-<name>-<pid> when possible, for memory and CPU
+                      limits (not --group, which groups the --list output)
+  --all               Run the command on every GPU at once and report each exit code
+  --sequential        With --all, run the command on one GPU after another
+  --card <n>          Use the GPU behind /dev/dri/card<n>
+  --index <n>         Use the n-th discrete GPU, counted from 0 in PCI slot order
+  --vendor <vendor>   Only consider nvidia, amd or intel GPUs; --index then counts
+                      that vendor's discrete GPUs
+  --device-id <vendor:device>
+                      Only consider GPUs with this PCI ID, e.g. 10de:2504; --index then
+                      counts the matching GPUs
+  --igpu, --offload off
+                      Run on integrated graphics without any offload variables
+  --interactive       Ask which GPU to use when several are equally preferred
+  --isolate           Run the command in its own process group, stopping its whole
+                      process tree on timeout or when primer exits
+  --kill-on-unplug    Stop the command if its GPU is removed (exit code 69)
+  --list              List detected GPUs and exit, with -v also shows PCIe topology
+  --list-drivers      Print the kernel, GL and Vulkan driver of each GPU and exit
+  --bug-report[=<file>]
+                      Print the config, GPUs, drivers and session details for a bug
+                      report, or write them to <file>, and exit
+  --connected-only    Only list GPUs with a display connected to one of their outputs
+  --group             Group the list by vendor, with a GPU count per vendor
+  --dump-default-config
+                      Print a commented config with every key at its default and exit
+  --emit-wrapper <path>
+                      Write a shell script that runs \"$@\" with the selected GPU's env
+  --exec              Replace primer with the command instead of starting it as a child
+  --explain           Show how the GPUs ranked and why the selected one was chosen
+  --force             Allow --emit-wrapper to overwrite an existing file
+  --json              Print detected GPUs as JSON and exit, same as --format json
+  --format <format>   How --list prints GPUs: table (default), plain (tab separated,
+                      no colors) or json
+  --info              Print everything udev knows about each GPU and exit
+  --output <connector> Use the GPU driving a connected display, e.g. DP-1 or HDMI-A-1
+                      as named in /sys/class/drm
+  --nice <n>          Run the command at niceness n, from -20 to 19; levels below 0
+                      usually need root
+  --ionice <class>    Run the command at the I/O priority idle, best-effort[:level] or
+                      realtime[:level], levels from 0 to 7; realtime needs root
+  --strict-vendor     Fail instead of leaving out display controllers primer can't tell
+                      the vendor of
+  --no-wait           Exit as soon as the command started instead of waiting for it
+  --no-fallback       Fail if the preferred vendor's GPU isn't present instead of using
+                      the next one in gpu_priority
+  --power-save        Let the kernel suspend idle discrete GPUs (or just the one given
+                      with --index) and exit, refusing if one is in use
+  --power-on          Keep discrete GPUs powered on and exit, undoing --power-save
+  --preset <name>     Apply the variables of the [preset.<name>] config section
+  --config-profile <name>
+                      Use the keys of the [profile.<name>] config section over those of
+                      [general]
+  --round-robin       Use the discrete GPUs in turn, each launch taking the one after
+                      the GPU the previous --round-robin launch used
+  -q, --quiet         Only print errors
+  --user <user>[:<group>]
+                      Run the command as another user, and optionally with another
+                      primary group; needs root
+  -v, --verbose       Print extra information about what primer is doing
+  --render-fd         Open the GPU's render node and pass it to the command as the file
+                      descriptor in PRIMER_RENDER_FD
+  --software, --llvmpipe
+                      Render on the CPU with llvmpipe and lavapipe instead of a GPU
+  --status <command>  Show whether <command> is running under primer, and on which GPU
+  --stats             Print the command's wall and CPU time, peak memory and exit code
+                      after it exits
+  --timings           Print how long GPU detection, selection and spawning took
+  --timeout <secs>    Stop the command if it runs longer than this (exit code 124)
+  --wait-for-gpu <secs>
+                      Wait up to this long for a GPU matching --vendor and --device-id
+                      (or any discrete GPU) to be plugged in before launching
+  --warmup            Wake the GPU from runtime suspend before starting the command
+  --vulkan-uuid       Export the GPU's Vulkan device UUID as PRIMER_VK_DEVICE_UUID";
+
+/// What `--power-save` and `--power-on` ask for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Power {
+    Save,
+    On,
+}
+
+/// Where `--capture` sends the command's output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    /// A timestamped file in `~/.config/primer/logs`.
+    Auto,
+    File(PathBuf),
+}
+
+/// How the GPU list is printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Table,
+    /// One tab separated line per GPU, without colors or a header.
+    Plain,
+    Json,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// User environment overrides, in the order they were given.
+    pub env: env::EnvVars,
+    /// Keys given to `--append-env`.
+    pub append_env: Vec<String>,
+    pub keep_env: Vec<String>,
+    pub drop_env: Vec<String>,
+    pub index: Option<usize>,
+    pub all: bool,
+    pub sequential: bool,
+    pub card: Option<u32>,
+    pub capture: Option<Capture>,
+    pub cgroup: Option<String>,
+    pub vendor: Option<Vendor>,
+    /// PCI vendor and device ID given to `--device-id`.
+    pub device_id: Option<(u16, u16)>,
+    /// `--igpu` or `--offload off`.
+    pub igpu: bool,
+    pub interactive: bool,
+    pub isolate: bool,
+    pub kill_on_unplug: bool,
+    pub list: bool,
+    pub connected_only: bool,
+    pub list_drivers: bool,
+    /// `Some(None)` prints the report, `Some(Some(path))` writes it to a file.
+    pub bug_report: Option<Option<PathBuf>>,
+    pub group: bool,
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+    pub no_fallback: bool,
+    pub strict_vendor: bool,
+    pub no_wait: bool,
+    pub output: Option<String>,
+    pub info: bool,
+    pub format: Format,
+    pub dump_default_config: bool,
+    pub emit_wrapper: Option<PathBuf>,
+    pub exec: bool,
+    pub explain: bool,
+    pub force: bool,
+    pub power: Option<Power>,
+    pub preset: Option<String>,
+    /// Read before the config is loaded, see `Config::open_profile`.
+    pub config_profile: Option<String>,
+    pub round_robin: bool,
+    pub quiet: bool,
+    pub render_fd: bool,
+    /// Given to `--user`, looked up when the command is launched.
+    pub user: Option<String>,
+    pub verbose: bool,
+    pub timeout: Option<Duration>,
+    pub software: bool,
+    pub status: Option<String>,
+    pub timings: bool,
+    pub stats: bool,
+    pub warmup: bool,
+    pub wait_for_gpu: Option<Duration>,
+    pub vulkan_uuid: bool,
+    pub command: Vec<String>,
+}
+
+impl Options {
+    /// See [`command_name`].
+    pub fn command_name(&self) -> Option<String> {
+        self.command.first().map(|program| command_name(program))
+    }
+    pub fn log_level(&self) -> log::Level {
+        if self.verbose {
+            log::Level::Verbose
+        } else if self.quiet {
+            log::Level::Quiet
+        } else {
+            log::Level::Normal
+        }
+    }
+    pub fn parse(args: Vec<String>) -> Result<Self, Error> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => break,
+                "--env" => {
+                    let pair = value(&mut args, &arg)?;
+                    let pair = env::parse_pair(&pair)
+                        .map_err(|reason| Error::InvalidArgument(format!("--env: {reason}")))?;
+                    options.env.push(pair);
+                }
+                "--env-file" => {
+                    let path = PathBuf::from(value(&mut args, &arg)?);
+                    options.env.extend(env::read_env_file(&path)?);
+                }
+                "--append-env" => {
+                    let key = value(&mut args, &arg)?;
+                    env::check_key(&key).map_err(|reason| {
+                        Error::InvalidArgument(format!("--append-env: {reason}"))
+                    })?;
+                    options.append_env.push(key);
+                }
+                "--keep-env" | "--drop-env" => {
+                    let key = value(&mut args, &arg)?;
+                    env::check_key(&key)
+                        .map_err(|reason| Error::InvalidArgument(format!("{arg}: {reason}")))?;
+                    if arg == "--keep-env" {
+                        options.keep_env.push(key);
+                    } else {
+                        options.drop_env.push(key);
+                    }
+                }
+                "--index" => {
+                    let index = value(&mut args, &arg)?;
+                    options.index = Some(index.parse().map_err(|_| {
+                        Error::InvalidArgument(format!("--index expects a number, got \"{index}\""))
+                    })?);
+                }
+                // the value is attached with `=` since a bare --capture is followed by the command
+                "--capture" => options.capture = Some(Capture::Auto),
+                _ if arg.starts_with("--capture=") => {
+                    let path = &arg["--capture=".len()..];
+                    if path.is_empty() {
+                        return Err(Error::InvalidArgument(String::from(
+                            "--capture= requires a file name",
+                        )));
+                    }
+                    options.capture = Some(Capture::File(PathBuf::from(path)));
+                }
+                "--cgroup" => {
+                    let name = value(&mut args, &arg)?;
+                    cgroup::check_name(&name)
+                        .map_err(|reason| Error::InvalidArgument(format!("--cgroup: {reason}")))?;
+                    options.cgroup = Some(name);
+                }
+                "--card" => {
+                    let card = value(&mut args, &arg)?;
+                    options.card = Some(card.parse().map_err(|_| {
+                        Error::InvalidArgument(format!("--card expects a number, got \"{card}\""))
+                    })?);
+                }
+                "--vendor" => {
+                    let vendor = value(&mut args, &arg)?;
+                    options.vendor = Some(Vendor::from_name(&vendor).ok_or_else(|| {
+                        Error::InvalidArgument(format!(
+                            "--vendor expects nvidia, amd or intel, got \"{vendor}\""
+                        ))
+                    })?);
+                }
+                "--device-id" => {
+                    let id = value(&mut args, &arg)?;
+                    let parse = |part: &str| u16::from_str_radix(part, 16).ok();
+                    options.device_id = id
+                        .split_once(':')
+                        .and_then(|(vendor, device)| Some((parse(vendor)?, parse(device)?)));
+                    if options.device_id.is_none() {
+                        return Err(Error::InvalidArgument(format!(
+                            "--device-id expects a PCI ID like 10de:2504, got \"{id}\""
+                        )));
+                    }
+                }
+                "--all" => options.all = true,
+                "--sequential" => options.sequential = true,
+                "--igpu" => options.igpu = true,
+                "--offload" => {
+                    let mode = value(&mut args, &arg)?;
+                    options.igpu = match mode.as_str() {
+                        "off" => true,
+                        "on" => false,
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "--offload expects on or off, got \"{mode}\""
+                            )))
+                        }
+                    };
+                }
+                "--interactive" => options.interactive = true,
+                "--isolate" => options.isolate = true,
+                "--kill-on-unplug" => options.kill_on_unplug = true,
+                "--list" => options.list = true,
+                "--connected-only" => options.connected_only = true,
+                "--list-drivers" => options.list_drivers = true,
+                // attached with `=` like --capture's file
+                "--bug-report" => options.bug_report = Some(None),
+                _ if arg.starts_with("--bug-report=") => {
+                    let path = &arg["--bug-report=".len()..];
+                    if path.is_empty() {
+                        return Err(Error::InvalidArgument(String::from(
+                            "--bug-report= requires a file name",
+                        )));
+                    }
+                    options.bug_report = Some(Some(PathBuf::from(path)));
+                }
+                "--group" => options.group = true,
+                "--info" => options.info = true,
+                "--json" => options.format = Format::Json,
+                "--format" => {
+                    let format = value(&mut args, &arg)?;
+                    options.format = match format.as_str() {
+                        "table" => Format::Table,
+                        "plain" => Format::Plain,
+                        "json" => Format::Json,
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "--format expects table, plain or json, got \"{format}\""
+                            )))
+                        }
+                    };
+                }
+                "--dump-default-config" => options.dump_default_config = true,
+                "--emit-wrapper" => {
+                    options.emit_wrapper = Some(PathBuf::from(value(&mut args, &arg)?))
+                }
+                "--exec" => options.exec = true,
+                "--explain" => options.explain = true,
+                "--force" => options.force = true,
+                "--nice" => {
+                    let level = value(&mut args, &arg)?;
+                    options.nice = Some(
+                        level
+                            .parse()
+                            .ok()
+                            .filter(|level| (-20..=19).contains(level))
+                            .ok_or_else(|| {
+                                Error::InvalidArgument(format!(
+                                    "--nice expects a number from -20 to 19, got \"{level}\""
+                                ))
+                            })?,
+                    );
+                }
+                "--ionice" => {
+                    let priority = value(&mut args, &arg)?;
+                    options.ionice =
+                        Some(IoPriority::parse(&priority).map_err(|reason| {
+                            Error::InvalidArgument(format!("--ionice: {reason}"))
+                        })?);
+                }
+                "--no-fallback" => options.no_fallback = true,
+                "--strict-vendor" => options.strict_vendor = true,
+                "--no-wait" => options.no_wait = true,
+                "--output" => options.output = Some(value(&mut args, &arg)?),
+                "--preset" => options.preset = Some(value(&mut args, &arg)?),
+                "--config-profile" => options.config_profile = Some(value(&mut args, &arg)?),
+                "--power-save" => options.power = Some(Power::Save),
+                "--power-on" => options.power = Some(Power::On),
+                "--round-robin" => options.round_robin = true,
+                "-q" | "--quiet" => options.quiet = true,
+                "--user" => options.user = Some(value(&mut args, &arg)?),
+                "-v" | "--verbose" => options.verbose = true,
+                "--timeout" => {
+                    let secs = value(&mut args, &arg)?;
+                    options.timeout = Some(
+                        secs.parse()
+                            .ok()
+                            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                            .ok_or_else(|| {
+                                Error::InvalidArgument(format!(
+                                    "--timeout expects a number of seconds, got \"{secs}\""
+                                ))
+                            })?,
+                    );
+                }
+                "--wait-for-gpu" => {
+                    let secs = value(&mut args, &arg)?;
+                    options.wait_for_gpu = Some(
+                        secs.parse()
+                            .ok()
+                            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                            .ok_or_else(|| {
+                                Error::InvalidArgument(format!(
+                                    "--wait-for-gpu expects a number of seconds, got \"{secs}\""
+                                ))
+                            })?,
+                    );
+                }
+                "--render-fd" => options.render_fd = true,
+                "--software" | "--llvmpipe" => options.software = true,
+                "--status" => options.status = Some(value(&mut args, &arg)?),
+                "--timings" => options.timings = true,
+                "--stats" => options.stats = true,
+                "--warmup" => options.warmup = true,
+                "--vulkan-uuid" => options.vulkan_uuid = true,
+                _ if arg.starts_with("--") => {
+                    return Err(Error::InvalidArgument(format!("unknown option \"{arg}\"")))
+                }
+                _ => {
+                    options.command.push(arg);
+                    break;
+                }
+            }
+        }
+        options.command.extend(args);
+        Ok(options)
+    }
+}
+
+/// Options of `primer bench`.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub vendor: Option<Vendor>,
+    /// Index into the discrete GPUs, as for `--index`.
+    pub index: Option<usize>,
+    /// How long each tool runs for.
+    pub duration: Duration,
+}
+
+impl BenchOptions {
+    pub fn parse(args: &[String]) -> Result<Self, Error> {
+        let mut options = BenchOptions {
+            vendor: None,
+            index: None,
+            duration: Duration::from_secs(10),
+        };
+        let mut args = args.iter().cloned();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--vendor" => {
+                    let vendor = value(&mut args, &arg)?;
+                    options.vendor = Some(Vendor::from_name(&vendor).ok_or_else(|| {
+                        Error::InvalidArgument(format!(
+                            "--vendor expects nvidia, amd or intel, got \"{vendor}\""
+                        ))
+                    })?);
+                }
+                "--index" => {
+                    let index = value(&mut args, &arg)?;
+                    options.index = Some(index.parse().map_err(|_| {
+                        Error::InvalidArgument(format!("--index expects a number, got \"{index}\""))
+                    })?);
+                }
+                "--duration" => {
+                    let secs = value(&mut args, &arg)?;
+                    options.duration = secs
+                        .parse()
+                        .ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| {
+                            Error::InvalidArgument(format!(
+                                "--duration expects a number of seconds, got \"{secs}\""
+                            ))
+                        })?;
+                }
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "unknown bench option \"{arg}\""
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// The name a command is matched by: `/usr/bin/mygame` becomes `mygame` and
+/// `C:\Games\MyGame.exe` becomes `MyGame`.
+pub fn command_name(program: &str) -> String {
+    let name = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    match name.len().checked_sub(".exe".len()) {
+        Some(end @ 1..)
+            if name.is_char_boundary(end) && name[end..].eq_ignore_ascii_case(".exe") =>
+        {
+            name[..end].to_string()
+        }
+        _ => name.to_string(),
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::InvalidArgument(format!("{flag} requires a value")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_name_strips_directories_and_exe() {
+        assert_eq!(command_name("/usr/bin/mygame"), "mygame");
+        assert_eq!(command_name("C:\\Games\\MyGame.exe"), "MyGame");
+        assert_eq!(command_name("./Game.EXE"), "Game");
+        assert_eq!(command_name("blender"), "blender");
+        assert_eq!(command_name(".exe"), ".exe");
+    }
+}
@@ -1,69 +1,1311 @@
-use std::path::PathBuf;
+use colored::Color;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use tini::Ini;
 
-use crate::{Error, Vendor};
+use crate::{env, env::EnvVars, log, process::IoPriority, toml, Error, Vendor};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuPriority {
+    /// Prefer any discrete GPU over integrated graphics, regardless of vendor.
+    Auto,
+    Vendors(Vec<Vendor>),
+    /// `vendor=weight` pairs, added up with how capable each GPU is into a score, see
+    /// `crate::weighted_score`.
+    Weights(Vec<(Vendor, u32)>),
+}
+
+/// How `DRI_PRIME` names the GPU, see `GPU::dri_prime_value`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DriPrimeFormat {
+    /// `pci-0000_01_00_0`
+    #[default]
+    Pci,
+    /// `pci-0000:01:00.0`
+    PciAddress,
+    /// `1`, the N of the GPU's `/dev/dri/cardN` node.
+    Index,
+}
+
+impl DriPrimeFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "pci" => Some(Self::Pci),
+            "pci_address" => Some(Self::PciAddress),
+            "index" => Some(Self::Index),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pci => "pci",
+            Self::PciAddress => "pci_address",
+            Self::Index => "index",
+        }
+    }
+}
+
+/// What to do when the command's GPU is reset while it runs, see `device::ResetMonitor`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GpuReset {
+    /// Don't watch for resets.
+    #[default]
+    Ignore,
+    /// Warn that the command may have lost its GPU and keep it running.
+    Log,
+    /// Stop the command and start it again on the recovered GPU.
+    Restart,
+}
+
+impl GpuReset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "ignore" => Some(Self::Ignore),
+            "log" => Some(Self::Log),
+            "restart" => Some(Self::Restart),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ignore => "ignore",
+            Self::Log => "log",
+            Self::Restart => "restart",
+        }
+    }
+}
+
+/// Colors used for GPU names, per vendor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colors {
+    pub nvidia: Color,
+    pub amd: Color,
+    pub intel: Color,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            nvidia: Color::Green,
+            amd: Color::Red,
+            intel: Color::Blue,
+        }
+    }
+}
+
+impl Colors {
+    pub fn get(&self, vendor: &Vendor) -> Color {
+        match vendor {
+            Vendor::NVIDIA => self.nvidia,
+            Vendor::AMD => self.amd,
+            Vendor::Intel => self.intel,
+        }
+    }
+}
+
+/// Parses a color name like `bright_green` or `bright green`.
+fn parse_color(key: &str, value: &str) -> Result<Color, Error> {
+    value
+        .trim()
+        .replace(['_', '-'], " ")
+        .parse()
+        .map_err(|_| Error::InvalidConfig(format!("{key}: \"{value}\" is not a color")))
+}
+
+/// Splits a comma separated list of names, ignoring empty entries.
+fn list(value: Option<String>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// INI files end values at `;` and `#`, so `gpu_priority = nvidia;amd` would quietly mean
+/// just `nvidia`. Lists are the values where that's an easy mistake to make.
+fn check_comment_separators(contents: &str) -> Result<(), Error> {
+    let mut section = "";
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = name.trim();
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if section == "general" || section.starts_with("profile.") => {
+                (key.trim(), value.trim())
+            }
+            _ => continue,
+        };
+        if !matches!(
+            key,
+            "gpu_priority" | "append_env" | "unset_env" | "keep_env" | "drop_env"
+        ) {
+            continue;
+        }
+        let (item, rest) = match value.split_once([';', '#']) {
+            Some(split) => split,
+            None => continue,
+        };
+        let rest = rest.trim_start_matches([';', '#']).trim();
+        // a comment has to be set off with whitespace and read as text, not as more items
+        let spaced = item.is_empty() || item.ends_with([' ', '\t']);
+        if !rest.is_empty() && (!spaced || looks_like_item(key, rest)) {
+            return Err(Error::InvalidConfig(format!(
+                "{key}: \"{value}\" is cut off at '{}', which starts a comment, separate items with ','",
+                &value[item.len()..item.len() + 1]
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `text` after a comment separator in list `key` starts with something that could
+/// be one of its items, e.g. `amd` in `gpu_priority = nvidia; amd`.
+fn looks_like_item(key: &str, text: &str) -> bool {
+    let first = text.split(',').next().unwrap_or_default().trim();
+    if key == "gpu_priority" {
+        let vendor = first.split_once('=').map_or(first, |(vendor, _)| vendor);
+        first.eq_ignore_ascii_case("auto") || Vendor::from_name(vendor).is_some()
+    } else {
+        env::check_key(first).is_ok()
+    }
+}
+
+/// A `[general]` key's value as TOML, lists as arrays and flags and numbers bare.
+fn toml_value(key: &str, value: String) -> String {
+    match key {
+        "gpu_priority" | "append_env" | "unset_env" | "keep_env" | "drop_env" => format!(
+            "[{}]",
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(toml::quote)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        "nice" => value,
+        _ if value == "true" || value == "false" => value,
+        _ => toml::quote(&value),
+    }
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::Black => "black",
+        Color::Red => "red",
+        Color::Green => "green",
+        Color::Yellow => "yellow",
+        Color::Blue => "blue",
+        Color::Magenta => "magenta",
+        Color::Cyan => "cyan",
+        Color::White => "white",
+        Color::BrightBlack => "bright_black",
+        Color::BrightRed => "bright_red",
+        Color::BrightGreen => "bright_green",
+        Color::BrightYellow => "bright_yellow",
+        Color::BrightBlue => "bright_blue",
+        Color::BrightMagenta => "bright_magenta",
+        Color::BrightCyan => "bright_cyan",
+        Color::BrightWhite | Color::TrueColor { .. } => "bright_white",
+    }
+}
+
+/// A named bundle of variables from a `[preset.<name>]` section, applied with `--preset`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preset {
+    /// Another preset applied first, from the section's `inherits` key.
+    pub inherits: Option<String>,
+    pub env: EnvVars,
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub first_use: bool,
-    pub gpu_priority: Vec<Vendor>,
+    pub gpu_priority: GpuPriority,
+    /// Preferred over every other vendor when present, ahead of `gpu_priority`.
+    pub default_vendor: Option<Vendor>,
+    /// Export the selected GPU's Vulkan device UUID to the command.
+    pub vulkan_uuid: bool,
+    /// Pass the command an open descriptor for the GPU's render node in `PRIMER_RENDER_FD`.
+    pub render_fd: bool,
+    /// Point wgpu at the selected GPU with `WGPU_BACKEND` and `WGPU_ADAPTER_NAME`.
+    pub wgpu_hints: bool,
+    /// Run commands in their own process group, see `process::Job`.
+    pub isolate: bool,
+    /// Wait for the command and exit with its exit code; otherwise primer exits once it started.
+    pub wait_for_child: bool,
+    /// Stop the command if its GPU is unplugged, e.g. an eGPU being disconnected.
+    pub kill_on_unplug: bool,
+    pub on_gpu_reset: GpuReset,
+    /// Tell the user when no discrete GPU was found and integrated graphics are used.
+    pub integrated_notice: bool,
+    /// Prefer integrated graphics while running on battery.
+    pub power_aware: bool,
+    /// Also log to the systemd journal as `primer`, see `log::journal`.
+    pub journal: bool,
+    /// Niceness the command runs at, `None` to inherit primer's.
+    pub nice: Option<i32>,
+    /// I/O priority the command runs at, `None` to inherit primer's.
+    pub ionice: Option<IoPriority>,
+    pub dri_prime_format: DriPrimeFormat,
+    /// Offload to integrated graphics as well when they don't drive any display.
+    pub reverse_prime: bool,
+    /// What `__VK_LAYER_NV_optimus` is set to on NVIDIA, `None` to leave it unset.
+    pub nvidia_vk_optimus: Option<String>,
+    pub colors: Colors,
+    /// Extra variables from the `[env]` section. Values may use `{pci}`, `{vendor}`, `{name}`
+    /// and `{render_node}` placeholders, filled in from the selected GPU.
+    pub env: EnvVars,
+    /// Variables whose value is prepended to the inherited one, see `env::append_separator`.
+    pub append_env: Vec<String>,
+    pub presets: HashMap<String, Preset>,
+    /// The preset applied to every launch, `--preset` replaces it.
+    pub preset: Option<String>,
+    /// `[profile.<name>]` sections as written, `[general]` keys selected with
+    /// `--config-profile`. Kept so saving the config doesn't drop them.
+    pub profiles: HashMap<String, EnvVars>,
+    /// Offload variables primer leaves out, e.g. `__VK_LAYER_NV_optimus` while troubleshooting.
+    pub unset_env: Vec<String>,
+    /// Inherited variables the command gets, all others are cleared. Empty keeps everything.
+    pub keep_env: Vec<String>,
+    /// Inherited variables the command doesn't get.
+    pub drop_env: Vec<String>,
+    /// Where the `[general]` keys that aren't at their default were set, for
+    /// `primer config show`.
+    pub origins: HashMap<&'static str, Origin>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             first_use: true,
-            gpu_priority: vec![Vendor::NVIDIA, Vendor::AMD, Vendor::Intel],
+            gpu_priority: GpuPriority::Vendors(vec![Vendor::NVIDIA, Vendor::AMD, Vendor::Intel]),
+            default_vendor: None,
+            vulkan_uuid: false,
+            render_fd: false,
+            wgpu_hints: false,
+            isolate: false,
+            wait_for_child: true,
+            kill_on_unplug: false,
+            on_gpu_reset: GpuReset::default(),
+            integrated_notice: true,
+            power_aware: false,
+            journal: false,
+            nice: None,
+            ionice: None,
+            dri_prime_format: DriPrimeFormat::default(),
+            reverse_prime: false,
+            nvidia_vk_optimus: Some(String::from("NVIDIA_only")),
+            colors: Colors::default(),
+            env: Vec::new(),
+            append_env: Vec::new(),
+            unset_env: Vec::new(),
+            keep_env: Vec::new(),
+            drop_env: Vec::new(),
+            presets: HashMap::new(),
+            preset: None,
+            profiles: HashMap::new(),
+            origins: HashMap::new(),
         }
     }
 }
 
 impl Config {
     pub fn open() -> Result<Self, super::Error> {
+        Self::open_profile(None)
+    }
+    /// Like `open`, with the keys of `[profile.<profile>]` used over those of `[general]`.
+    pub fn open_profile(profile: Option<&str>) -> Result<Self, super::Error> {
         let path = config_path();
-        std::fs::create_dir_all(&path.parent().unwrap())?;
-        if !std::fs::try_exists(&path)? {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        if !std::fs::exists(&path)? {
             std::fs::File::create(&path)?;
         }
-
-        let ini = Ini::from_file(&config_path())?;
+        Self::load_profile(&path, profile)
+    }
+    /// Loads an INI config, or a TOML one if `path` ends in `.toml`.
+    pub fn load(path: &Path) -> Result<Self, super::Error> {
+        Self::load_profile(path, None)
+    }
+    /// Like `load`, with the keys of `[profile.<profile>]` used over those of `[general]`.
+    pub fn load_profile(path: &Path, profile: Option<&str>) -> Result<Self, super::Error> {
+        Self::from_source(&Source::read(path)?, profile)
+    }
+    fn from_source(source: &Source, profile: Option<&str>) -> Result<Self, super::Error> {
+        let profile = profile.map(|name| format!("profile.{name}"));
+        if let Some(section) = profile
+            .as_ref()
+            .filter(|section| !source.section_names().contains(section))
+        {
+            return Err(Error::InvalidConfig(format!(
+                "there is no profile \"{}\", add a [{section}] section",
+                &section["profile.".len()..]
+            )));
+        }
+        let get = |key: &str| {
+            profile
+                .as_ref()
+                .and_then(|section| source.get(section, key))
+                .or_else(|| source.get("general", key))
+        };
+        let flag = |key: &str, default: bool| match get(key) {
+            Some(value) => value.trim().parse().map_err(|_| {
+                Error::InvalidConfig(format!("{key}: \"{value}\" is not true or false"))
+            }),
+            None => Ok(default),
+        };
+        let env_keys = |key: &str| {
+            let keys = list(get(key));
+            for name in &keys {
+                env::check_key(name)
+                    .map_err(|reason| Error::InvalidConfig(format!("{key}: {reason}")))?;
+            }
+            Ok::<_, Error>(keys)
+        };
+        let known = Config::default().general();
+        let mut origins = HashMap::new();
+        let keys = known
+            .iter()
+            .map(|(key, _)| *key)
+            .chain(OPTIONAL_KEYS.iter().copied());
+        for key in keys {
+            let origin = match &profile {
+                Some(section) if source.get(section, key).is_some() => {
+                    Origin::Profile(section["profile.".len()..].to_string())
+                }
+                _ if source.get("general", key).is_some() => Origin::File,
+                _ => continue,
+            };
+            origins.insert(key, origin);
+        }
+        let mut profiles = HashMap::new();
+        for section in source.section_names() {
+            let is_profile = section.starts_with("profile.");
+            if section != "general" && !is_profile {
+                continue;
+            }
+            let items = source.section(&section);
+            for (key, _) in &items {
+                if !known.iter().any(|(k, _)| k == key) && !OPTIONAL_KEYS.contains(&key.as_str()) {
+                    log::warn(format!(
+                        "Ignoring unknown config key \"{key}\" in [{section}]"
+                    ));
+                }
+            }
+            if is_profile {
+                profiles.insert(section["profile.".len()..].to_string(), items);
+            }
+        }
+        let env = source.section("env");
+        for (key, _) in &env {
+            env::check_key(key)
+                .map_err(|reason| Error::InvalidConfig(format!("[env]: {reason}")))?;
+        }
+        let mut presets = HashMap::new();
+        for section in source.section_names() {
+            let name = match section.strip_prefix("preset.") {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let mut preset = Preset::default();
+            for (key, value) in source.section(&section) {
+                if key == "inherits" {
+                    preset.inherits = Some(value.trim().to_string());
+                    continue;
+                }
+                env::check_key(&key)
+                    .map_err(|reason| Error::InvalidConfig(format!("[{section}]: {reason}")))?;
+                preset.env.push((key, value));
+            }
+            presets.insert(name, preset);
+        }
+        for (name, preset) in &presets {
+            if let Some(parent) = preset
+                .inherits
+                .as_ref()
+                .filter(|p| !presets.contains_key(*p))
+            {
+                return Err(Error::InvalidConfig(format!(
+                    "[preset.{name}] inherits \"{parent}\", which doesn't exist"
+                )));
+            }
+        }
+        let defaults = Colors::default();
+        let color = |key: &str, default: Color| match get(key) {
+            Some(value) => parse_color(key, &value),
+            None => Ok(default),
+        };
+        let colors = Colors {
+            nvidia: color("nvidia_color", defaults.nvidia)?,
+            amd: color("amd_color", defaults.amd)?,
+            intel: color("intel_color", defaults.intel)?,
+        };
+        let gpu_priority = get("gpu_priority").unwrap_or(String::from("nvidia, amd, intel"));
+        let gpu_priority = if gpu_priority.trim().eq_ignore_ascii_case("auto") {
+            GpuPriority::Auto
+        } else if gpu_priority.contains('=') {
+            let weights = list(Some(gpu_priority.clone()));
+            GpuPriority::Weights(
+                weights
+                    .iter()
+                    .map(|pair| {
+                        let fail = || {
+                            Error::InvalidConfig(format!(
+                                "gpu_priority: \"{pair}\" in \"{gpu_priority}\" is not vendor=weight, e.g. nvidia=3; give every vendor a weight or none"
+                            ))
+                        };
+                        let (vendor, weight) = pair.split_once('=').ok_or_else(fail)?;
+                        let vendor = Vendor::from_name(vendor).ok_or_else(fail)?;
+                        let weight = weight.trim().parse().map_err(|_| fail())?;
+                        Ok((vendor, weight))
+                    })
+                    .collect::<Result<_, Error>>()?,
+            )
+        } else {
+            let vendors = list(Some(gpu_priority.clone()));
+            GpuPriority::Vendors(
+                vendors
+                    .iter()
+                    .map(|vendor| {
+                        Vendor::from_name(vendor).ok_or_else(|| {
+                            Error::InvalidConfig(format!(
+                                "gpu_priority: \"{vendor}\" in \"{gpu_priority}\" is not nvidia, amd or intel, list vendors separated by ','"
+                            ))
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            )
+        };
+        let gpu_priority = match gpu_priority {
+            GpuPriority::Vendors(vendors) => {
+                GpuPriority::Vendors(first_of_each_vendor(vendors, |vendor| vendor))
+            }
+            GpuPriority::Weights(weights) => {
+                GpuPriority::Weights(first_of_each_vendor(weights, |(vendor, _)| vendor))
+            }
+            auto => auto,
+        };
+        let default_vendor = match get("default_vendor").filter(|vendor| !vendor.trim().is_empty())
+        {
+            Some(vendor) => Some(Vendor::from_name(&vendor).ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "default_vendor: \"{vendor}\" is not nvidia, amd or intel"
+                ))
+            })?),
+            None => None,
+        };
+        let nice = match get("nice").filter(|nice| !nice.trim().is_empty()) {
+            Some(nice) => Some(
+                nice.trim()
+                    .parse()
+                    .ok()
+                    .filter(|nice| (-20..=19).contains(nice))
+                    .ok_or_else(|| {
+                        Error::InvalidConfig(format!(
+                            "nice: \"{nice}\" is not a number from -20 to 19"
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        let ionice = match get("ionice").filter(|ionice| !ionice.trim().is_empty()) {
+            Some(ionice) => Some(
+                IoPriority::parse(&ionice)
+                    .map_err(|reason| Error::InvalidConfig(format!("ionice: {reason}")))?,
+            ),
+            None => None,
+        };
+        let nvidia_vk_optimus = match get("nvidia_vk_optimus").as_deref().map(str::trim) {
+            Some("none") => None,
+            Some(value @ ("NVIDIA_only" | "non_NVIDIA_only")) => Some(value.to_string()),
+            Some(value) => {
+                log::warn(format!(
+                    "nvidia_vk_optimus: \"{value}\" is not NVIDIA_only, non_NVIDIA_only or none, using NVIDIA_only"
+                ));
+                Some(String::from("NVIDIA_only"))
+            }
+            None => Some(String::from("NVIDIA_only")),
+        };
+        let dri_prime_format = match get("dri_prime_format") {
+            Some(format) => DriPrimeFormat::from_name(&format).ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "dri_prime_format: \"{format}\" is not pci, pci_address or index"
+                ))
+            })?,
+            None => DriPrimeFormat::default(),
+        };
+        let on_gpu_reset = match get("on_gpu_reset") {
+            Some(action) => GpuReset::from_name(&action).ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "on_gpu_reset: \"{action}\" is not ignore, log or restart"
+                ))
+            })?,
+            None => GpuReset::default(),
+        };
         Ok(Self {
-            first_use: ini.get("general", "first_use").unwrap_or(true),
-            gpu_priority: ini
-                .get::<String>("general", "gpu_priority")
-                .unwrap_or(String::from("nvidia, amd, intel"))
-                .split(",")
-                .into_iter()
-                .filter_map(|vendor| match vendor.to_ascii_lowercase().trim() {
-                    "nvidia" => Some(Vendor::NVIDIA),
-                    "amd" => Some(Vendor::AMD),
-                    "intel" => Some(Vendor::Intel),
-                    _ => None,
-                })
-                .collect(),
+            first_use: flag("first_use", true)?,
+            gpu_priority,
+            default_vendor,
+            vulkan_uuid: flag("vulkan_uuid", false)?,
+            render_fd: flag("render_fd", false)?,
+            wgpu_hints: flag("wgpu_hints", false)?,
+            isolate: flag("isolate", false)?,
+            wait_for_child: flag("wait_for_child", true)?,
+            kill_on_unplug: flag("kill_on_unplug", false)?,
+            on_gpu_reset,
+            integrated_notice: flag("integrated_notice", true)?,
+            power_aware: flag("power_aware", false)?,
+            journal: flag("journal", false)?,
+            nice,
+            ionice,
+            dri_prime_format,
+            reverse_prime: flag("reverse_prime", false)?,
+            nvidia_vk_optimus,
+            colors,
+            env,
+            append_env: env_keys("append_env")?,
+            unset_env: env_keys("unset_env")?,
+            keep_env: env_keys("keep_env")?,
+            drop_env: env_keys("drop_env")?,
+            presets,
+            preset: get("preset").filter(|preset| !preset.trim().is_empty()),
+            profiles,
+            origins,
         })
     }
-    pub fn save(&self) -> Result<(), super::Error> {
-        Ini::new()
-            .section("general")
-            .item("first_use", false)
-            .item_vec(
-                "gpu_priority",
-                &self
-                    .gpu_priority
+    /// The `[general]` keys with the values in effect and where each one came from, for
+    /// `primer config show`.
+    pub fn effective(&self) -> Vec<(&'static str, String, Origin)> {
+        let mut general = self.general();
+        // written out as false, since saving is what ends the first use
+        general[0].1 = self.first_use.to_string();
+        general
+            .into_iter()
+            .map(|(key, value)| {
+                let origin = self.origins.get(key).cloned().unwrap_or(Origin::Default);
+                (key, value, origin)
+            })
+            .collect()
+    }
+    /// The `[general]` keys `save` writes: `first_use`, the ones set in the file and any that
+    /// were changed from their default. Writing the rest would make them look set on the next
+    /// load, and keep them from following changes to the defaults.
+    fn saved_general(&self) -> Vec<(&'static str, String)> {
+        let defaults = Config::default().general();
+        self.general()
+            .into_iter()
+            .filter(|(key, value)| {
+                *key == "first_use"
+                    || self.origins.contains_key(key)
+                    || !defaults.contains(&(*key, value.clone()))
+            })
+            .collect()
+    }
+    /// The `[general]` keys as they're written out. Lists are comma separated.
+    fn general(&self) -> Vec<(&'static str, String)> {
+        let mut general = vec![("first_use", String::from("false"))];
+        general.push((
+            "gpu_priority",
+            match &self.gpu_priority {
+                GpuPriority::Auto => String::from("auto"),
+                GpuPriority::Vendors(vendors) => vendors
                     .iter()
                     .map(|v| v.to_string())
-                    .collect::<Vec<String>>(),
-            )
-            .to_file(config_path().as_path())
-            .map_err(|e| Error::Io(e))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                GpuPriority::Weights(weights) => weights
+                    .iter()
+                    .map(|(vendor, weight)| {
+                        format!("{}={weight}", vendor.to_string().to_lowercase())
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            },
+        ));
+        if let Some(vendor) = &self.default_vendor {
+            general.push(("default_vendor", vendor.to_string()));
+        }
+        general.extend([
+            ("vulkan_uuid", self.vulkan_uuid.to_string()),
+            ("render_fd", self.render_fd.to_string()),
+            ("wgpu_hints", self.wgpu_hints.to_string()),
+            ("isolate", self.isolate.to_string()),
+            ("wait_for_child", self.wait_for_child.to_string()),
+            ("kill_on_unplug", self.kill_on_unplug.to_string()),
+            ("on_gpu_reset", self.on_gpu_reset.name().to_string()),
+            ("integrated_notice", self.integrated_notice.to_string()),
+            ("power_aware", self.power_aware.to_string()),
+            ("journal", self.journal.to_string()),
+            ("dri_prime_format", self.dri_prime_format.name().to_string()),
+            ("reverse_prime", self.reverse_prime.to_string()),
+            (
+                "nvidia_vk_optimus",
+                self.nvidia_vk_optimus
+                    .clone()
+                    .unwrap_or_else(|| String::from("none")),
+            ),
+            ("nvidia_color", color_name(self.colors.nvidia).to_string()),
+            ("amd_color", color_name(self.colors.amd).to_string()),
+            ("intel_color", color_name(self.colors.intel).to_string()),
+            ("append_env", self.append_env.join(", ")),
+            ("unset_env", self.unset_env.join(", ")),
+            ("keep_env", self.keep_env.join(", ")),
+            ("drop_env", self.drop_env.join(", ")),
+        ]);
+        if let Some(nice) = self.nice {
+            general.push(("nice", nice.to_string()));
+        }
+        if let Some(ionice) = &self.ionice {
+            general.push(("ionice", ionice.name()));
+        }
+        if let Some(preset) = &self.preset {
+            general.push(("preset", preset.clone()));
+        }
+        general
+    }
+    /// Every key with its value in INI form, each with a comment saying what it does. Keys
+    /// without a value are included commented out.
+    pub fn template(&self) -> String {
+        let mut general = self.general();
+        for &key in OPTIONAL_KEYS {
+            if !general.iter().any(|(k, _)| *k == key) {
+                general.push((key, String::new()));
+            }
+        }
+        let mut out = String::from(
+            "# primer config, see `primer --help` for the options these correspond to\n[general]\n",
+        );
+        for (key, value) in general {
+            if let Some((_, doc)) = KEY_DOCS.iter().find(|(k, _)| *k == key) {
+                for line in doc.lines() {
+                    out.push_str(&format!("# {line}\n"));
+                }
+            }
+            match (key, value.is_empty()) {
+                ("default_vendor", true) => out.push_str("# default_vendor = nvidia\n"),
+                ("preset", true) => out.push_str("# preset = proton\n"),
+                ("nice", true) => out.push_str("# nice = 10\n"),
+                ("ionice", true) => out.push_str("# ionice = idle\n"),
+                _ => out.push_str(&format!("{key} = {value}\n")),
+            }
+            out.push('\n');
+        }
+        out.push_str(
+            "# Variables set for every launch. Values can use {pci}, {vendor}, {name} and\n# {render_node}.\n[env]\n",
+        );
+        for (key, value) in &self.env {
+            out.push_str(&format!("{key} = {value}\n"));
+        }
+        out.push_str("# MY_RENDER_DEVICE = {render_node}\n\n");
+        out.push_str(
+            "# Named sets of variables, applied with --preset <name>. `inherits` applies another\n# preset first.\n# [preset.proton]\n# inherits = debug\n# PROTON_LOG = 1\n\n# Named sets of [general] keys used over those above, selected with\n# --config-profile <name>.\n# [profile.battery]\n# gpu_priority = intel\n",
+        );
+        out
     }
+    fn to_toml(&self) -> String {
+        let mut out = String::from("[general]\n");
+        for (key, value) in self.saved_general() {
+            out.push_str(&format!("{key} = {}\n", toml_value(key, value)));
+        }
+        out.push_str("\n[env]\n");
+        for (key, value) in &self.env {
+            out.push_str(&format!("{} = {}\n", toml::key(key), toml::quote(value)));
+        }
+        for (name, preset) in self.sorted_presets() {
+            out.push_str(&format!("\n[preset.{}]\n", toml::key(name)));
+            if let Some(parent) = &preset.inherits {
+                out.push_str(&format!("inherits = {}\n", toml::quote(parent)));
+            }
+            for (key, value) in &preset.env {
+                out.push_str(&format!("{} = {}\n", toml::key(key), toml::quote(value)));
+            }
+        }
+        for (name, items) in self.sorted_profiles() {
+            out.push_str(&format!("\n[profile.{}]\n", toml::key(name)));
+            for (key, value) in items {
+                let value = toml_value(key, value.clone());
+                out.push_str(&format!("{} = {value}\n", toml::key(key)));
+            }
+        }
+        out
+    }
+    /// Writes the config back to `config.toml` if that is the one in use, `config.ini` otherwise.
+    pub fn save(&self) -> Result<(), super::Error> {
+        // keep the previous config around and never leave a half-written file behind
+        let path = config_path();
+        if std::fs::metadata(&path).is_ok_and(|meta| meta.len() > 0) {
+            std::fs::copy(&path, backup_path())?;
+        }
+        let tmp = temp_path();
+        self.write_to(&tmp, is_toml(&path))?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+    /// Writes the config to `path`, as TOML or INI.
+    fn write_to(&self, path: &Path, toml: bool) -> Result<(), super::Error> {
+        if toml {
+            std::fs::write(path, self.to_toml())?;
+        } else {
+            let ini = self
+                .saved_general()
+                .into_iter()
+                .fold(Ini::new().section("general"), |ini, (key, value)| {
+                    ini.item(key, value)
+                })
+                .section("env")
+                .items(self.env.iter().cloned());
+            let ini = self
+                .sorted_presets()
+                .into_iter()
+                .fold(ini, |ini, (name, preset)| {
+                    let ini = ini.section(format!("preset.{name}"));
+                    let ini = match &preset.inherits {
+                        Some(parent) => ini.item("inherits", parent),
+                        None => ini,
+                    };
+                    ini.items(preset.env.iter().cloned())
+                });
+            let ini = self
+                .sorted_profiles()
+                .into_iter()
+                .fold(ini, |ini, (name, items)| {
+                    ini.section(format!("profile.{name}"))
+                        .items(items.iter().cloned())
+                });
+            ini.to_file(path)?;
+        }
+        Ok(())
+    }
+    fn sorted_presets(&self) -> Vec<(&String, &Preset)> {
+        let mut presets: Vec<(&String, &Preset)> = self.presets.iter().collect();
+        presets.sort_by_key(|(name, _)| *name);
+        presets
+    }
+    fn sorted_profiles(&self) -> Vec<(&String, &EnvVars)> {
+        let mut profiles: Vec<(&String, &EnvVars)> = self.profiles.iter().collect();
+        profiles.sort_by_key(|(name, _)| *name);
+        profiles
+    }
+    /// The variables of preset `name`, after those of the presets it inherits from, resolved
+    /// when a preset is used so a broken chain only matters to launches that need it.
+    pub fn preset_env(&self, name: &str) -> Result<EnvVars, Error> {
+        let mut chain = Vec::new();
+        let mut next = Some(name);
+        while let Some(name) = next {
+            if chain.contains(&name) {
+                chain.push(name);
+                return Err(Error::InvalidConfig(format!(
+                    "presets inherit from each other in a loop: {}",
+                    chain.join(" -> ")
+                )));
+            }
+            let preset = self
+                .presets
+                .get(name)
+                .ok_or_else(|| Error::InvalidConfig(format!("there is no preset \"{name}\"")))?;
+            chain.push(name);
+            next = preset.inherits.as_deref();
+        }
+        let mut vars = EnvVars::new();
+        for name in chain.into_iter().rev() {
+            for (key, value) in &self.presets[name].env {
+                env::set(&mut vars, key, value);
+            }
+        }
+        Ok(vars)
+    }
+    /// Puts the backup made by the last `save` back in place, refusing if it isn't a valid config.
+    pub fn restore() -> Result<Self, super::Error> {
+        let backup = backup_path();
+        if !std::fs::exists(&backup)? {
+            return Err(Error::InvalidConfig(format!(
+                "there is no backup at \"{}\"",
+                backup.display()
+            )));
+        }
+        // the backup is `config.toml.bak` or `config.ini.bak`, in the format of the config
+        let config = Source::read_as(&backup, is_toml(&config_path()))
+            .and_then(|source| Self::from_source(&source, None))
+            .map_err(|err| {
+                let reason = match err {
+                    Error::InvalidConfig(reason) => reason,
+                    err => err.to_string(),
+                };
+                Error::InvalidConfig(format!(
+                    "not restoring \"{}\", it doesn't parse: {reason}",
+                    backup.display()
+                ))
+            })?;
+        std::fs::copy(&backup, config_path())?;
+        Ok(config)
+    }
+}
+
+/// `[general]` keys that are only written out when they're set.
+const OPTIONAL_KEYS: &[&str] = &["default_vendor", "nice", "ionice", "preset"];
+
+/// What each `[general]` key does, for `Config::template`.
+const KEY_DOCS: &[(&str, &str)] = &[
+    ("first_use", "false once primer has shown its welcome message"),
+    (
+        "gpu_priority",
+        "Vendors in the order they are preferred, `auto` for any discrete GPU over\nintegrated graphics, or weights like nvidia=3, amd=2, intel=1 that add up with\nbeing discrete (+1) and VRAM (+1 per 8 GiB) into a score",
+    ),
+    (
+        "default_vendor",
+        "A vendor preferred over every other one when present, ahead of gpu_priority",
+    ),
+    (
+        "vulkan_uuid",
+        "Export the GPU's Vulkan device UUID as PRIMER_VK_DEVICE_UUID (NVIDIA only)",
+    ),
+    (
+        "render_fd",
+        "Pass an open descriptor for the GPU's render node in PRIMER_RENDER_FD",
+    ),
+    ("wgpu_hints", "Set WGPU_BACKEND and WGPU_ADAPTER_NAME for wgpu programs"),
+    (
+        "isolate",
+        "Run commands in their own process group and stop the whole group when primer exits",
+    ),
+    (
+        "wait_for_child",
+        "Wait for the command and exit with its exit code, false to exit once it started",
+    ),
+    ("kill_on_unplug", "Stop the command if its GPU is removed"),
+    (
+        "on_gpu_reset",
+        "When the command's GPU is reset or re-added: ignore, log a warning, or restart\nthe command (needs isolate)",
+    ),
+    (
+        "integrated_notice",
+        "Say so when no discrete GPU was found and integrated graphics are used",
+    ),
+    (
+        "power_aware",
+        "Use integrated graphics while on battery, unless a GPU is picked with --index,\n--card or --output",
+    ),
+    (
+        "journal",
+        "Also send messages and launches to the systemd journal, see journalctl -t primer",
+    ),
+    (
+        "dri_prime_format",
+        "How DRI_PRIME names the GPU: pci (pci-0000_01_00_0), pci_address (pci-0000:01:00.0)\nor index (the N of /dev/dri/cardN)",
+    ),
+    (
+        "reverse_prime",
+        "Set DRI_PRIME for integrated graphics too when the displays are connected to a\ndiscrete GPU, so programs render on the integrated GPU and show on the other",
+    ),
+    (
+        "nvidia_vk_optimus",
+        "__VK_LAYER_NV_optimus on NVIDIA: NVIDIA_only, non_NVIDIA_only to keep Vulkan off\nthe NVIDIA GPU, or none to leave it unset",
+    ),
+    ("nvidia_color", "Color of NVIDIA GPU names, e.g. green or bright_cyan"),
+    ("amd_color", "Color of AMD GPU names"),
+    ("intel_color", "Color of Intel GPU names"),
+    (
+        "append_env",
+        "Variables whose value is put in front of the inherited one instead of replacing it",
+    ),
+    (
+        "unset_env",
+        "Offload variables primer should leave out, e.g. __VK_LAYER_NV_optimus",
+    ),
+    (
+        "keep_env",
+        "Inherited variables commands get, all others are cleared; empty keeps them all",
+    ),
+    ("drop_env", "Inherited variables commands don't get"),
+    (
+        "nice",
+        "Niceness to run commands at, from -20 to 19; below 0 needs root",
+    ),
+    (
+        "ionice",
+        "I/O priority to run commands at: idle, best-effort[:0-7] or realtime[:0-7]",
+    ),
+    ("preset", "A preset applied to every launch"),
+];
+
+/// Drops repeated vendors from `gpu_priority`, keeping where each one is listed first. Only
+/// the first entry ever counts, so a repeat is most likely a typo worth pointing out.
+fn first_of_each_vendor<T>(items: Vec<T>, vendor: impl Fn(&T) -> &Vendor) -> Vec<T> {
+    let mut kept: Vec<T> = Vec::with_capacity(items.len());
+    for item in items {
+        if kept.iter().any(|seen| vendor(seen) == vendor(&item)) {
+            log::warn(format!(
+                "gpu_priority lists {} more than once, only the first one counts",
+                vendor(&item)
+            ));
+            continue;
+        }
+        kept.push(item);
+    }
+    kept
 }
 
-fn config_path() -> PathBuf {
+/// Where the value of a key in effect came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    /// `[general]` in the config file.
+    File,
+    /// The `[profile.<name>]` section selected with `--config-profile`.
+    Profile(String),
+    /// A command line option, e.g. `--nice` for `nice`.
+    Cli,
+}
+
+impl Origin {
+    pub fn name(&self) -> String {
+        match self {
+            Origin::Default => String::from("default"),
+            Origin::File => String::from("file"),
+            Origin::Profile(name) => format!("profile.{name}"),
+            Origin::Cli => String::from("command line"),
+        }
+    }
+}
+
+/// Where a config is read from and how.
+enum Source {
+    Ini(Ini),
+    Toml(toml::Document),
+}
+
+impl Source {
+    fn read(path: &Path) -> Result<Self, Error> {
+        Self::read_as(path, is_toml(path))
+    }
+    /// Reads `path` as TOML or INI whatever its name, for backups named after the config.
+    fn read_as(path: &Path, toml: bool) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+        if toml {
+            Ok(Source::Toml(toml::parse(&contents).map_err(|reason| {
+                Error::InvalidConfig(format!("{}: {reason}", path.display()))
+            })?))
+        } else {
+            check_comment_separators(&contents)?;
+            Ok(Source::Ini(Ini::from_string(&contents)?))
+        }
+    }
+    fn get(&self, section: &str, key: &str) -> Option<String> {
+        match self {
+            Source::Ini(ini) => ini.get(section, key),
+            Source::Toml(doc) => doc.get(section, key).map(String::from),
+        }
+    }
+    fn section_names(&self) -> Vec<String> {
+        match self {
+            Source::Ini(ini) => ini.iter().map(|(name, _)| name.clone()).collect(),
+            Source::Toml(doc) => doc.table_names().map(String::from).collect(),
+        }
+    }
+    fn section(&self, name: &str) -> EnvVars {
+        match self {
+            Source::Ini(ini) => ini
+                .section_iter(name)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            Source::Toml(doc) => doc.table(name).to_vec(),
+        }
+    }
+}
+
+/// `~/.config/primer`, where the config and captured output live.
+pub fn primer_dir() -> PathBuf {
     let path = std::env::var("HOME").unwrap_or(String::from("./"));
     PathBuf::from(path)
         .canonicalize()
         .unwrap()
-        .join(".config/primer/config.ini")
+        .join(".config/primer")
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+/// `config.toml` when it exists, `config.ini` otherwise.
+pub fn config_path() -> PathBuf {
+    let toml = primer_dir().join("config.toml");
+    if toml.exists() {
+        toml
+    } else {
+        primer_dir().join("config.ini")
+    }
+}
+
+fn backup_path() -> PathBuf {
+    let path = config_path();
+    path.with_extension(if is_toml(&path) {
+        "toml.bak"
+    } else {
+        "ini.bak"
+    })
+}
+
+fn temp_path() -> PathBuf {
+    let path = config_path();
+    path.with_extension(if is_toml(&path) {
+        "toml.tmp"
+    } else {
+        "ini.tmp"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `config` the way `save` does and loads it back.
+    fn round_trip(config: &Config, toml: bool) -> Config {
+        let path = std::env::temp_dir().join(format!(
+            "primer-test-{}-{}",
+            std::process::id(),
+            if toml { "config.toml" } else { "config.ini" }
+        ));
+        config.write_to(&path, toml).unwrap();
+        let loaded = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap()
+    }
+
+    fn customized() -> Config {
+        let mut config = Config {
+            first_use: false,
+            gpu_priority: GpuPriority::Vendors(vec![Vendor::AMD, Vendor::NVIDIA]),
+            nice: Some(-5),
+            render_fd: true,
+            env: vec![
+                (String::from("DXVK_HUD"), String::from("fps, memory")),
+                (String::from("MY_DEVICE"), String::from("{render_node}")),
+            ],
+            unset_env: vec![String::from("__VK_LAYER_NV_optimus")],
+            ..Config::default()
+        };
+        config.presets.insert(
+            String::from("debug"),
+            Preset {
+                inherits: None,
+                env: vec![(String::from("MESA_DEBUG"), String::from("1"))],
+            },
+        );
+        config.profiles.insert(
+            String::from("battery"),
+            vec![
+                (String::from("gpu_priority"), String::from("intel, amd")),
+                (String::from("power_aware"), String::from("true")),
+            ],
+        );
+        config
+    }
+
+    fn assert_same(loaded: &Config, config: &Config) {
+        assert_eq!(loaded.gpu_priority, config.gpu_priority);
+        assert_eq!(loaded.nice, config.nice);
+        assert_eq!(loaded.render_fd, config.render_fd);
+        assert_eq!(loaded.env, config.env);
+        assert_eq!(loaded.unset_env, config.unset_env);
+        assert_eq!(loaded.presets["debug"].env, config.presets["debug"].env);
+        assert_eq!(loaded.profiles, config.profiles);
+        assert_eq!(loaded.general(), config.general());
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let config = customized();
+        assert_same(&round_trip(&config, true), &config);
+    }
+
+    #[test]
+    fn toml_round_trip_with_odd_names_and_values() {
+        let mut config = customized();
+        config
+            .env
+            .push((String::from("PS1"), String::from("\u{1b}[1m$\r\t")));
+        let battery = config.profiles.remove("battery").unwrap();
+        config.profiles.insert(String::from("my game"), battery);
+        let loaded = round_trip(&config, true);
+        assert_same(&loaded, &config);
+        assert_eq!(loaded.profiles, config.profiles);
+    }
+
+    #[test]
+    fn ini_round_trip() {
+        let config = customized();
+        assert_same(&round_trip(&config, false), &config);
+    }
+
+    #[test]
+    fn toml_profiles_apply_over_general() {
+        let config = customized();
+        let path =
+            std::env::temp_dir().join(format!("primer-test-{}-profile.toml", std::process::id()));
+        config.write_to(&path, true).unwrap();
+        let loaded = Config::load_profile(&path, Some("battery"));
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            loaded.gpu_priority,
+            GpuPriority::Vendors(vec![Vendor::Intel, Vendor::AMD])
+        );
+        assert!(loaded.power_aware);
+        // keys the profile doesn't set keep their [general] value
+        assert_eq!(loaded.nice, Some(-5));
+    }
+
+    #[test]
+    fn origins_follow_where_keys_are_set() {
+        let config = customized();
+        let path =
+            std::env::temp_dir().join(format!("primer-test-{}-origins.ini", std::process::id()));
+        config.write_to(&path, false).unwrap();
+        let loaded = Config::load_profile(&path, Some("battery"));
+        std::fs::remove_file(&path).unwrap();
+        let origins: HashMap<&str, Origin> = loaded
+            .unwrap()
+            .effective()
+            .into_iter()
+            .map(|(key, _, origin)| (key, origin))
+            .collect();
+        assert_eq!(
+            origins["gpu_priority"],
+            Origin::Profile(String::from("battery"))
+        );
+        assert_eq!(
+            origins["power_aware"],
+            Origin::Profile(String::from("battery"))
+        );
+        assert_eq!(origins["nice"], Origin::File);
+        assert_eq!(origins["isolate"], Origin::Default);
+    }
+
+    #[test]
+    fn saving_leaves_out_defaults_that_were_not_set() {
+        let saved = customized().saved_general();
+        let keys: Vec<&str> = saved.iter().map(|(key, _)| *key).collect();
+        assert_eq!(
+            keys,
+            [
+                "first_use",
+                "gpu_priority",
+                "render_fd",
+                "unset_env",
+                "nice"
+            ]
+        );
+        let mut config = Config::default();
+        config.origins.insert("isolate", Origin::File);
+        assert!(config
+            .saved_general()
+            .contains(&("isolate", String::from("false"))));
+    }
+
+    fn preset(inherits: Option<&str>, env: &[(&str, &str)]) -> Preset {
+        Preset {
+            inherits: inherits.map(String::from),
+            env: env
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn presets_apply_their_parents_first() {
+        let mut config = Config::default();
+        config.presets.insert(
+            String::from("base"),
+            preset(None, &[("MESA_DEBUG", "1"), ("DXVK_HUD", "fps")]),
+        );
+        config.presets.insert(
+            String::from("debug"),
+            preset(Some("base"), &[("DXVK_HUD", "full")]),
+        );
+        config.presets.insert(
+            String::from("proton"),
+            preset(Some("debug"), &[("PROTON_LOG", "1")]),
+        );
+        let vars = config.preset_env("proton").unwrap();
+        let vars: Vec<(&str, &str)> = vars
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            vars,
+            [
+                ("MESA_DEBUG", "1"),
+                ("DXVK_HUD", "full"),
+                ("PROTON_LOG", "1")
+            ]
+        );
+    }
+
+    #[test]
+    fn preset_cycles_are_an_error() {
+        let mut config = Config::default();
+        config
+            .presets
+            .insert(String::from("a"), preset(Some("b"), &[]));
+        config
+            .presets
+            .insert(String::from("b"), preset(Some("a"), &[]));
+        match config.preset_env("a") {
+            Err(Error::InvalidConfig(msg)) => assert!(msg.ends_with("a -> b -> a"), "{msg}"),
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn list_items_after_comment_separators_are_an_error() {
+        for value in [
+            "nvidia;amd",
+            "nvidia; amd",
+            "nvidia ; amd",
+            "nvidia # amd=2",
+        ] {
+            let contents = format!("[general]\ngpu_priority = {value}\n");
+            assert!(
+                matches!(
+                    check_comment_separators(&contents),
+                    Err(Error::InvalidConfig(_))
+                ),
+                "{value}"
+            );
+        }
+        assert!(check_comment_separators("[general]\ndrop_env = FOO ; BAR\n").is_err());
+        // real comments are fine
+        for line in [
+            "gpu_priority = nvidia, amd ; laptop without the eGPU",
+            "gpu_priority = nvidia # prefer the dGPU",
+            "drop_env = FOO ; no longer needed",
+            "unset_env = FOO;",
+        ] {
+            let contents = format!("[general]\n{line}\n");
+            assert!(check_comment_separators(&contents).is_ok(), "{line}");
+        }
+    }
+
+    #[test]
+    fn template_comments_every_key() {
+        let mut keys: Vec<&str> = Config::default()
+            .general()
+            .iter()
+            .map(|(k, _)| *k)
+            .collect();
+        keys.extend(OPTIONAL_KEYS);
+        for key in keys {
+            assert!(
+                KEY_DOCS.iter().any(|(k, _)| *k == key),
+                "{key} has no comment in the template"
+            );
+        }
+    }
 }
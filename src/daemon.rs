@@ -0,0 +1,398 @@
+//! `primer daemon`: serves the GPU list, live stats and launches on the session bus, so panels
+//! and launchers don't have to run `primer --json` over and over. The list is enumerated once
+//! and again only when udev reports a DRM device coming or going, which also emits
+//! `GpusChanged`.
+
+use std::os::fd::AsRawFd;
+use std::process::{Child, Stdio};
+
+use crate::dbus::{self, Arg, Message};
+use crate::{
+    cli, config, device, discrete_gpus, discrete_index, find_gpus, json, log, record_launch,
+    sort_by_rank, state, Error, GPU,
+};
+
+pub const BUS_NAME: &str = "io.github.zurrty.Primer";
+pub const PATH: &str = "/io/github/zurrty/Primer";
+pub const INTERFACE: &str = "io.github.zurrty.Primer";
+
+const UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const UNKNOWN_OBJECT: &str = "org.freedesktop.DBus.Error.UnknownObject";
+const INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+const NO_SUCH_GPU: &str = "io.github.zurrty.Primer.Error.NoSuchGpu";
+const LAUNCH_FAILED: &str = "io.github.zurrty.Primer.Error.LaunchFailed";
+
+/// How long the daemon sleeps between checks for exited launches, in milliseconds.
+const REAP_INTERVAL_MS: i32 = 1000;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.github.zurrty.Primer">
+    <method name="ListGpus">
+      <arg name="gpus" type="s" direction="out"/>
+    </method>
+    <method name="GetStats">
+      <arg name="pci" type="s" direction="in"/>
+      <arg name="stats" type="s" direction="out"/>
+    </method>
+    <method name="LaunchOn">
+      <arg name="pci" type="s" direction="in"/>
+      <arg name="command" type="as" direction="in"/>
+      <arg name="pid" type="u" direction="out"/>
+    </method>
+    <signal name="GpusChanged">
+      <arg name="gpus" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+pub fn run(args: &[String], config: config::Config) -> Result<i32, Error> {
+    if let Some(arg) = args.first() {
+        return Err(Error::InvalidArgument(format!(
+            "daemon takes no arguments, got \"{arg}\""
+        )));
+    }
+    // listening before enumerating, so a GPU added in between isn't missed
+    let monitor = device::HotplugMonitor::new()?;
+    let mut daemon = Daemon::new(scan(&config)?, config);
+    let mut bus = dbus::Connection::session()?;
+    bus.request_name(BUS_NAME)?;
+    log::info(format!(
+        "Serving {BUS_NAME} on the session bus as {}",
+        bus.unique_name
+    ));
+    loop {
+        // messages read while waiting for a reply are invisible to poll
+        while bus.has_pending() {
+            daemon.serve(&mut bus)?;
+        }
+        let mut fds = [
+            libc::pollfd {
+                fd: bus.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: monitor.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // SAFETY: fds is a valid array of pollfd for the duration of the call
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, REAP_INTERVAL_MS) };
+        if ready < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+        daemon.reap();
+        if fds[1].revents & libc::POLLIN != 0 && monitor.changed() {
+            let gpus = scan(&daemon.config)?;
+            if daemon.replace_gpus(gpus) {
+                log::info("The GPUs changed");
+                let signal = Message::signal(PATH, INTERFACE, "GpusChanged")
+                    .with_args(&[Arg::Str(daemon.list_json())]);
+                bus.send(signal)?;
+            }
+        }
+        if fds[0].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match daemon.serve(&mut bus) {
+                // the bus closes the connection when the session ends
+                Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    log::info("The session bus went away");
+                    return Ok(0);
+                }
+                result => result?,
+            }
+        }
+    }
+}
+
+fn scan(config: &config::Config) -> Result<Vec<GPU>, Error> {
+    let mut gpus = match find_gpus(&device::Udev) {
+        // an eGPU may still be plugged in
+        Err(Error::DeviceNotFound) => Vec::new(),
+        gpus => gpus?,
+    };
+    sort_by_rank(&mut gpus, config);
+    Ok(gpus)
+}
+
+struct Daemon {
+    config: config::Config,
+    gpus: Vec<GPU>,
+    /// Programs started by `LaunchOn`, reaped as they exit.
+    children: Vec<Child>,
+}
+
+impl Daemon {
+    fn new(gpus: Vec<GPU>, config: config::Config) -> Self {
+        Self {
+            config,
+            gpus,
+            children: Vec::new(),
+        }
+    }
+    /// Reads one message from the bus and answers it.
+    fn serve(&mut self, bus: &mut dbus::Connection) -> Result<(), Error> {
+        let message = bus.receive()?;
+        if let Some(reply) = self.handle(&message) {
+            bus.send(reply)?;
+        }
+        Ok(())
+    }
+    /// The reply to `message`, `None` for anything but method calls wanting one.
+    fn handle(&mut self, message: &Message) -> Option<Message> {
+        if message.kind != dbus::METHOD_CALL {
+            return None;
+        }
+        let reply = match self.answer(message) {
+            Ok(args) => Message::reply_to(message).with_args(&args),
+            Err((name, text)) => Message::error_reply(message, name, &text),
+        };
+        (message.flags & dbus::NO_REPLY_EXPECTED == 0).then_some(reply)
+    }
+    fn answer(&mut self, call: &Message) -> Result<Vec<Arg>, (&'static str, String)> {
+        let member = call.member.as_deref().unwrap_or_default();
+        let interface = call.interface.as_deref().unwrap_or(INTERFACE);
+        match (interface, member) {
+            ("org.freedesktop.DBus.Peer", "Ping") => return Ok(Vec::new()),
+            ("org.freedesktop.DBus.Introspectable", "Introspect") => {
+                return Ok(vec![Arg::Str(String::from(INTROSPECTION))])
+            }
+            _ => {}
+        }
+        if call.path.as_deref() != Some(PATH) {
+            return Err((
+                UNKNOWN_OBJECT,
+                format!("No object at {}", call.path.as_deref().unwrap_or_default()),
+            ));
+        }
+        let args = call
+            .args()
+            .map_err(|err| (INVALID_ARGS, format!("Can't read the arguments: {err}")))?;
+        match (interface, member, args.as_slice()) {
+            (INTERFACE, "ListGpus", []) => Ok(vec![Arg::Str(self.list_json())]),
+            (INTERFACE, "GetStats", [Arg::Str(pci)]) => {
+                let gpu = self.find(pci)?;
+                Ok(vec![Arg::Str(self.stats_json(gpu))])
+            }
+            (INTERFACE, "LaunchOn", [Arg::Str(pci), Arg::StrArray(command)]) => {
+                let child = launch(self.find(pci)?, command, &self.config)
+                    .map_err(|err| (LAUNCH_FAILED, err.to_string()))?;
+                let pid = child.id();
+                self.children.push(child);
+                Ok(vec![Arg::U32(pid)])
+            }
+            (INTERFACE, "ListGpus" | "GetStats" | "LaunchOn", _) => Err((
+                INVALID_ARGS,
+                format!("Wrong arguments \"{}\" for {member}", call.signature()),
+            )),
+            _ => Err((
+                UNKNOWN_METHOD,
+                format!("No method {member} in interface {interface}"),
+            )),
+        }
+    }
+    /// The GPU with this PCI address, in either the `0000:01:00.0` or the `--json` form.
+    fn find(&self, pci: &str) -> Result<&GPU, (&'static str, String)> {
+        self.gpus
+            .iter()
+            .find(|gpu| gpu.pci_address() == Some(pci) || gpu.pci_slot().as_deref() == Some(pci))
+            .ok_or_else(|| (NO_SUCH_GPU, format!("No GPU at {pci}")))
+    }
+    /// Takes the newly enumerated `gpus`, and whether they're a different set than before.
+    fn replace_gpus(&mut self, gpus: Vec<GPU>) -> bool {
+        let addresses = |gpus: &[GPU]| -> Vec<Option<String>> {
+            gpus.iter()
+                .map(|gpu| gpu.pci_address().map(String::from))
+                .collect()
+        };
+        let changed = addresses(&gpus) != addresses(&self.gpus);
+        self.gpus = gpus;
+        changed
+    }
+    /// The GPU list as `primer --list --json` prints it.
+    fn list_json(&self) -> String {
+        let discrete = discrete_gpus(&self.gpus);
+        let list: Vec<json::Object> = self
+            .gpus
+            .iter()
+            .map(|gpu| gpu.to_json(discrete_index(&discrete, gpu)))
+            .collect();
+        json::Value::to_json(&list)
+    }
+    fn stats_json(&self, gpu: &GPU) -> String {
+        let pci = gpu.pci_slot().unwrap_or_default();
+        let launches: Vec<json::Object> = state::running()
+            .into_iter()
+            .filter(|launch| launch.pci == pci)
+            .map(|launch| {
+                json::Object::new()
+                    .field("pid", launch.pid)
+                    .field("command", launch.command)
+            })
+            .collect();
+        let discrete = discrete_gpus(&self.gpus);
+        let stats = gpu
+            .to_json(discrete_index(&discrete, gpu))
+            .field("runtime_status", gpu.runtime_status())
+            .field("vram_total", gpu.vram_total())
+            .field("launches", launches);
+        json::Value::to_json(&stats)
+    }
+    /// Collects launches that exited, so they don't linger as zombies or in `--status`.
+    fn reap(&mut self) {
+        self.children.retain_mut(|child| match child.try_wait() {
+            Ok(None) => true,
+            _ => {
+                state::forget(child.id());
+                false
+            }
+        });
+    }
+}
+
+/// Starts `command` on `gpu` like a launch from a terminal would, in its own process group so
+/// signals meant for the daemon don't reach it.
+fn launch(gpu: &GPU, command: &[String], config: &config::Config) -> Result<Child, Error> {
+    let (program, args) = command.split_first().ok_or(Error::EmptyCommand)?;
+    let mut cmd = gpu.build_command(program, args, &[], config)?;
+    cmd.stdin(Stdio::null());
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    let child = cmd.spawn()?;
+    record_launch(&state::Launch {
+        pid: child.id(),
+        command: cli::command_name(program),
+        gpu: gpu.name.clone(),
+        pci: gpu.pci_slot().unwrap_or_default(),
+    });
+    Ok(child)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceInfo;
+    use std::path::PathBuf;
+
+    /// Like the display controllers in `tests/common`, at sysfs paths that don't exist.
+    fn display(slot: &str, id: &str, driver: &str, name: &str) -> DeviceInfo {
+        DeviceInfo {
+            syspath: PathBuf::from("/nonexistent/primer-test").join(slot),
+            driver: Some(driver.to_string()),
+            properties: [
+                ("PCI_CLASS", "30000"),
+                ("PCI_ID", id),
+                ("PCI_SLOT_NAME", slot),
+                ("ID_MODEL_FROM_DATABASE", name),
+            ]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+            parent: None,
+        }
+    }
+
+    fn daemon() -> Daemon {
+        let devices = vec![
+            display("0000:00:02.0", "8086:A7A0", "i915", "Raptor Lake-P"),
+            display("0000:01:00.0", "10DE:2560", "nvidia", "GA106M"),
+        ];
+        let config = config::Config::default();
+        let mut gpus = find_gpus(&devices).unwrap();
+        sort_by_rank(&mut gpus, &config);
+        Daemon::new(gpus, config)
+    }
+
+    fn call(member: &str, args: &[Arg]) -> Message {
+        let mut call = Message::call(BUS_NAME, PATH, INTERFACE, member).with_args(args);
+        call.serial = 5;
+        call.sender = Some(String::from(":1.7"));
+        call
+    }
+
+    fn reply(daemon: &mut Daemon, call: &Message) -> (Option<String>, Vec<Arg>) {
+        let reply = daemon.handle(call).unwrap();
+        assert_eq!(reply.reply_serial, Some(5));
+        assert_eq!(reply.destination.as_deref(), Some(":1.7"));
+        (reply.error_name.clone(), reply.args().unwrap())
+    }
+
+    #[test]
+    fn list_gpus_returns_the_json_list() {
+        let mut daemon = daemon();
+        let (error, args) = reply(&mut daemon, &call("ListGpus", &[]));
+        assert_eq!(error, None);
+        let [Arg::Str(list)] = args.as_slice() else {
+            panic!("{args:?}")
+        };
+        assert!(list.starts_with("[{"), "{list}");
+        assert!(list.contains(r#""pci":"0000_01_00_0""#), "{list}");
+        assert_eq!(list, &daemon.list_json());
+    }
+
+    #[test]
+    fn get_stats_finds_gpus_by_either_address_form() {
+        let mut daemon = daemon();
+        for pci in ["0000:01:00.0", "0000_01_00_0"] {
+            let (error, args) = reply(&mut daemon, &call("GetStats", &[Arg::Str(pci.into())]));
+            assert_eq!(error, None, "{args:?}");
+            let [Arg::Str(stats)] = args.as_slice() else {
+                panic!("{args:?}")
+            };
+            assert!(stats.contains(r#""launches":["#), "{stats}");
+        }
+        let (error, _) = reply(
+            &mut daemon,
+            &call("GetStats", &[Arg::Str("0000:09:00.0".into())]),
+        );
+        assert_eq!(error.as_deref(), Some(NO_SUCH_GPU));
+    }
+
+    #[test]
+    fn bad_calls_get_error_replies() {
+        let mut daemon = daemon();
+        let (error, _) = reply(&mut daemon, &call("GetStats", &[Arg::U32(1)]));
+        assert_eq!(error.as_deref(), Some(INVALID_ARGS));
+        let (error, _) = reply(&mut daemon, &call("Frobnicate", &[]));
+        assert_eq!(error.as_deref(), Some(UNKNOWN_METHOD));
+        let (error, _) = reply(
+            &mut daemon,
+            &call(
+                "LaunchOn",
+                &[Arg::Str("0000:01:00.0".into()), Arg::StrArray(Vec::new())],
+            ),
+        );
+        assert_eq!(error.as_deref(), Some(LAUNCH_FAILED));
+
+        // callers that don't want a reply don't get one
+        let mut quiet = call("ListGpus", &[]);
+        quiet.flags = dbus::NO_REPLY_EXPECTED;
+        assert!(daemon.handle(&quiet).is_none());
+        // nor do signals
+        assert!(daemon
+            .handle(&Message::signal(PATH, INTERFACE, "GpusChanged"))
+            .is_none());
+    }
+
+    #[test]
+    fn only_a_different_set_of_gpus_is_a_change() {
+        let mut running = daemon();
+        assert!(!running.replace_gpus(daemon().gpus));
+        let mut fewer = daemon().gpus;
+        fewer.pop();
+        assert!(running.replace_gpus(fewer));
+    }
+}
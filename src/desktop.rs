@@ -0,0 +1,140 @@
+//! Running `.desktop` files, following the Exec key rules of the desktop entry spec.
+
+use std::path::Path;
+
+use crate::Error;
+
+/// Whether `program` names a desktop entry rather than an executable.
+pub fn is_desktop_file(program: &str) -> bool {
+    program.ends_with(".desktop") && Path::new(program).is_file()
+}
+
+/// The command line a desktop entry's `Exec` key runs, with `files` (the arguments after the
+/// entry on primer's command line) substituted for `%f`, `%F`, `%u` and `%U`.
+pub fn command(path: &Path, files: &[String]) -> Result<Vec<String>, Error> {
+    let fail = |reason: &str| Error::DesktopEntry(format!("{}: {reason}", path.display()));
+    let contents = std::fs::read_to_string(path)?;
+    let mut group = "";
+    let mut keys = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            group = name;
+        } else if let Some((key, value)) = line.split_once('=') {
+            if group == "Desktop Entry" {
+                keys.push((key.trim(), unescape_string(value.trim())));
+            }
+        }
+    }
+    let get = |key: &str| {
+        keys.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let exec = get("Exec").ok_or_else(|| fail("no Exec key in [Desktop Entry]"))?;
+    let mut command = Vec::new();
+    for arg in split_exec(exec).map_err(|reason| fail(&reason))? {
+        match arg.as_str() {
+            "%f" | "%u" => command.extend(files.first().cloned()),
+            "%F" | "%U" => command.extend(files.iter().cloned()),
+            "%i" => {
+                if let Some(icon) = get("Icon") {
+                    command.extend([String::from("--icon"), icon.to_string()]);
+                }
+            }
+            _ => {
+                let arg = expand_codes(&arg, get("Name").unwrap_or(""), path);
+                if !arg.is_empty() {
+                    command.push(arg);
+                }
+            }
+        }
+    }
+    if command.is_empty() {
+        return Err(fail("the Exec key is empty"));
+    }
+    Ok(command)
+}
+
+/// Undoes the `\s`, `\n`, `\t`, `\r` and `\\` escapes allowed in any string value.
+fn unescape_string(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('s' | 'n' | 't' | 'r' | '\\'))) => {
+                chars.next();
+                unescaped.push(match next {
+                    's' => ' ',
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    _ => '\\',
+                });
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Splits an Exec value into arguments. Arguments may be double quoted, and inside quotes
+/// `\"`, `` \` ``, `\$` and `\\` stand for the character itself.
+fn split_exec(exec: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = exec.chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next_if(|c| *c == ' ').is_some() {
+            continue;
+        }
+        let mut arg = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '`' | '$' | '\\')) => arg.push(c),
+                        Some(c) => {
+                            arg.push('\\');
+                            arg.push(c);
+                        }
+                        None => return Err(String::from("unterminated quote in Exec")),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err(String::from("unterminated quote in Exec")),
+                }
+            }
+            // a quoted argument stands alone, so %f inside quotes isn't a field code
+            args.push(arg.replace('%', "%%"));
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ' ') {
+                arg.push(c);
+            }
+            args.push(arg);
+        }
+    }
+    Ok(args)
+}
+
+/// Expands the field codes that can appear inside an argument. `%c` is the entry's name and
+/// `%k` its path; deprecated and file codes embedded in a larger argument are dropped.
+fn expand_codes(arg: &str, name: &str, path: &Path) -> String {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('c') => expanded.push_str(name),
+            Some('k') => expanded.push_str(&path.to_string_lossy()),
+            _ => (),
+        }
+    }
+    expanded
+}
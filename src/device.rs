@@ -0,0 +1,160 @@
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// The parts of a device primer looks at, read once during enumeration so the rest of
+/// primer never has to hold on to a live udev handle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub syspath: PathBuf,
+    pub driver: Option<String>,
+    /// udev properties in the order udev reports them.
+    pub properties: Vec<(String, String)>,
+    pub parent: Option<ParentInfo>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParentInfo {
+    pub subsystem: Option<String>,
+    pub sysname: String,
+    pub driver: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Somewhere to enumerate devices from: udev normally, or a fixed list.
+pub trait DeviceSource {
+    fn scan(&self) -> Result<Vec<DeviceInfo>, Error>;
+}
+
+/// Every device udev knows about that has a driver bound.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Udev;
+
+impl DeviceSource for Udev {
+    fn scan(&self) -> Result<Vec<DeviceInfo>, Error> {
+        let mut enumerator = udev::Enumerator::new()?;
+        Ok(enumerator
+            .scan_devices()?
+            .filter(|dev| dev.driver().is_some())
+            .map(|dev| DeviceInfo::from(&dev))
+            .collect())
+    }
+}
+
+impl DeviceSource for [DeviceInfo] {
+    fn scan(&self) -> Result<Vec<DeviceInfo>, Error> {
+        Ok(self.to_vec())
+    }
+}
+
+impl DeviceSource for Vec<DeviceInfo> {
+    fn scan(&self) -> Result<Vec<DeviceInfo>, Error> {
+        self.as_slice().scan()
+    }
+}
+
+impl From<&udev::Device> for DeviceInfo {
+    fn from(dev: &udev::Device) -> Self {
+        Self {
+            syspath: dev.syspath().to_path_buf(),
+            driver: dev.driver().map(|drv| drv.to_string_lossy().into_owned()),
+            properties: dev
+                .properties()
+                .map(|prop| {
+                    (
+                        prop.name().to_string_lossy().into_owned(),
+                        prop.value().to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+            parent: dev.parent().map(|parent| ParentInfo {
+                subsystem: parent
+                    .subsystem()
+                    .map(|subsystem| subsystem.to_string_lossy().into_owned()),
+                sysname: parent.sysname().to_string_lossy().into_owned(),
+                driver: parent
+                    .driver()
+                    .map(|drv| drv.to_string_lossy().into_owned()),
+            }),
+        }
+    }
+}
+
+/// Watches udev for the GPU at a sysfs path being reset: one of its DRM devices added again,
+/// or a change event with `RESET` or `WEDGED` set, which drivers send after recovering a hung
+/// GPU. Along with `HotplugMonitor`, the one place primer keeps a udev handle around, for as
+/// long as it waits.
+pub struct ResetMonitor {
+    socket: udev::MonitorSocket,
+    sysfs: PathBuf,
+}
+
+impl ResetMonitor {
+    pub fn new(sysfs: &Path) -> Result<Self, Error> {
+        let socket = udev::MonitorBuilder::new()?
+            .match_subsystem("drm")?
+            .listen()?;
+        Ok(Self {
+            socket,
+            sysfs: sysfs.to_path_buf(),
+        })
+    }
+    /// Whether the GPU was reset since the last call. Doesn't block.
+    pub fn reset(&self) -> bool {
+        // every pending event is read, so one reset isn't reported several times
+        self.socket.iter().fold(false, |reset, event| {
+            let ours = event.syspath().starts_with(&self.sysfs);
+            let recovered = match event.event_type() {
+                udev::EventType::Add => true,
+                udev::EventType::Change => {
+                    event.property_value("RESET").is_some()
+                        || event.property_value("WEDGED").is_some()
+                }
+                _ => false,
+            };
+            reset || (ours && recovered)
+        })
+    }
+}
+
+/// Watches udev for DRM devices being added or removed, so `primer daemon` can keep its GPU
+/// list current without enumerating again on every request.
+#[cfg(feature = "daemon")]
+pub struct HotplugMonitor {
+    socket: udev::MonitorSocket,
+}
+
+#[cfg(feature = "daemon")]
+impl HotplugMonitor {
+    pub fn new() -> Result<Self, Error> {
+        let socket = udev::MonitorBuilder::new()?
+            .match_subsystem("drm")?
+            .listen()?;
+        Ok(Self { socket })
+    }
+    /// Whether a DRM device came or went since the last call. Doesn't block.
+    pub fn changed(&self) -> bool {
+        self.socket.iter().fold(false, |changed, event| {
+            let hotplug = matches!(
+                event.event_type(),
+                udev::EventType::Add | udev::EventType::Remove
+            );
+            changed || hotplug
+        })
+    }
+}
+
+#[cfg(feature = "daemon")]
+impl std::os::fd::AsRawFd for HotplugMonitor {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.socket.as_raw_fd()
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::Path;
+
+use crate::Error;
+
+use crate::log;
+
+pub type EnvVars = Vec<(String, String)>;
+
+/// Variables primer sets itself. Overriding them is allowed but usually breaks offload.
+pub const OFFLOAD_VARS: &[&str] = &[
+    "DRI_PRIME",
+    "__NV_PRIME_RENDER_OFFLOAD",
+    "__VK_LAYER_NV_optimus",
+    "__GLX_VENDOR_LIBRARY_NAME",
+];
+
+/// Checks that `key` is a usable variable name, i.e. matches `[A-Za-z_][A-Za-z0-9_]*`.
+pub fn check_key(key: &str) -> Result<(), String> {
+    let mut chars = key.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("\"{key}\" is not a valid variable name"))
+    }
+}
+
+/// Whether primer sets `key` itself for offloading.
+pub fn is_reserved(key: &str) -> bool {
+    OFFLOAD_VARS.contains(&key)
+}
+
+/// Warns when a user-supplied variable replaces one primer manages.
+pub fn warn_if_reserved(key: &str) {
+    if is_reserved(key) {
+        log::warn(format!(
+            "{key} is set by primer for GPU offload, overriding it may stop offload from working."
+        ));
+    }
+}
+
+/// Sets `key`, replacing an earlier value in place so each variable appears once.
+pub fn set(vars: &mut EnvVars, key: impl Into<String>, value: impl Into<String>) {
+    let (key, value) = (key.into(), value.into());
+    match vars.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, v)) => *v = value,
+        None => vars.push((key, value)),
+    }
+}
+
+/// Warns about offload variables already in primer's own environment that `vars` replaces.
+pub fn warn_if_inherited(vars: &[(String, String)]) {
+    for (key, value) in vars {
+        if !OFFLOAD_VARS.contains(&key.as_str()) {
+            continue;
+        }
+        if let Some(inherited) = std::env::var(key)
+            .ok()
+            .filter(|inherited| inherited != value)
+        {
+            log::warn(format!(
+                "{key} is already set to \"{inherited}\", primer is overriding it with \"{value}\"."
+            ));
+        }
+    }
+}
+
+/// What joins an appended value to the inherited one: `;` for `DXVK_CONFIG`, `,` for
+/// `VKD3D_CONFIG` and `:` for everything else, which covers search paths like `VK_ICD_FILENAMES`.
+pub fn append_separator(key: &str) -> &'static str {
+    match key {
+        "DXVK_CONFIG" => ";",
+        "VKD3D_CONFIG" => ",",
+        _ => ":",
+    }
+}
+
+/// Puts primer's value for each of `keys` in front of the one inherited from primer's own
+/// environment instead of replacing it.
+pub fn prepend_inherited(vars: &mut EnvVars, keys: &[String]) {
+    for (key, value) in vars.iter_mut() {
+        if !keys.contains(key) {
+            continue;
+        }
+        if let Some(inherited) = std::env::var(&*key)
+            .ok()
+            .filter(|inherited| !inherited.is_empty())
+        {
+            *value = format!("{value}{}{inherited}", append_separator(key));
+        }
+    }
+}
+
+/// Quotes `value` for a POSIX shell.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Parses a single `KEY=VALUE` pair, as given to `--env` or found in an env file.
+pub fn parse_pair(pair: &str) -> Result<(String, String), String> {
+    let pair = pair.trim();
+    let pair = pair.strip_prefix("export ").unwrap_or(pair);
+    let (key, value) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, found \"{pair}\""))?;
+    let key = key.trim();
+    check_key(key)?;
+    let value = value.trim();
+    let value = match (value.chars().next(), value.chars().last()) {
+        (Some(q @ ('"' | '\'')), Some(end)) if value.len() > 1 && q == end => {
+            &value[1..value.len() - 1]
+        }
+        _ => value,
+    };
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Reads a dotenv-style file of `KEY=VALUE` lines. Blank lines and `#` comments are skipped.
+pub fn read_env_file(path: &Path) -> Result<EnvVars, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| Error::EnvFile(format!("Can't read {}: {err}", path.display())))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(i, line)| {
+            parse_pair(line)
+                .map_err(|reason| Error::EnvFile(format!("{}:{}: {reason}", path.display(), i + 1)))
+        })
+        .collect()
+}
+
+/// Expands `{placeholder}`s in a config value using `lookup`. `{{` and `}}` produce literal braces.
+pub fn expand(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                expanded.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                expanded.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or_else(|| {
+                    Error::EnvTemplate(format!("unterminated placeholder in \"{value}\""))
+                })?;
+                let name = &rest[..end];
+                let replacement = lookup(name).ok_or_else(|| {
+                    Error::EnvTemplate(format!(
+                        "{{{name}}} in \"{value}\" is not a known placeholder or has no value for this GPU"
+                    ))
+                })?;
+                expanded.push_str(&replacement);
+                chars = rest[end + 1..].chars();
+            }
+            _ => expanded.push(c),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        for key in ["", "1GPU", "MY VAR", "MY-VAR", "DRI_PRIME="] {
+            assert!(check_key(key).is_err(), "{key:?} was accepted");
+        }
+        assert!(parse_pair("MY VAR=1").is_err());
+        assert!(parse_pair("NO_VALUE").is_err());
+    }
+
+    #[test]
+    fn valid_keys_are_accepted() {
+        for key in [
+            "A",
+            "_private",
+            "DXVK_HUD",
+            "__GL_SYNC_TO_VBLANK",
+            "mesa_debug2",
+        ] {
+            assert_eq!(check_key(key), Ok(()));
+        }
+        assert_eq!(
+            parse_pair("export PROTON_LOG = \"1\""),
+            Ok((String::from("PROTON_LOG"), String::from("1")))
+        );
+    }
+
+    #[test]
+    fn reserved_key_override_is_warned_about_and_applied() {
+        assert!(is_reserved("DRI_PRIME"));
+        assert!(!is_reserved("DXVK_HUD"));
+        let mut vars = vec![(String::from("DRI_PRIME"), String::from("pci-0000_01_00_0"))];
+        warn_if_reserved("DRI_PRIME");
+        set(&mut vars, "DRI_PRIME", "1");
+        assert_eq!(vars, vec![(String::from("DRI_PRIME"), String::from("1"))]);
+    }
+}
@@ -0,0 +1,2625 @@
+//! PRIME GPU offload helper: finds the GPUs in a system and launches commands on one of them.
+
+pub mod bench;
+pub mod cgroup;
+pub mod cli;
+pub mod config;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+#[cfg(feature = "daemon")]
+pub mod dbus;
+pub mod desktop;
+pub mod device;
+pub mod env;
+pub mod json;
+pub mod pci_ids;
+pub mod process;
+pub mod report;
+pub mod state;
+pub mod table;
+pub mod toml;
+pub mod vulkan;
+
+use colored::*;
+use device::{DeviceInfo, DeviceSource};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{IsTerminal, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Ini(tini::Error),
+    DeviceNotFound,
+    InvalidDevice,
+    EmptyCommand,
+    InvalidArgument(String),
+    EnvFile(String),
+    EnvTemplate(String),
+    NoMatchingGpu(String),
+    InvalidConfig(String),
+    VulkanUuidUnavailable,
+    UnsupportedVendor(Vendor),
+    Power(String),
+    DesktopEntry(String),
+    /// Talking to the session bus failed, for `primer daemon`.
+    DBus(String),
+    /// Display controllers `--strict-vendor` couldn't map to a vendor, one per line.
+    UnrecognizedDevices(Vec<String>),
+}
+
+/// The message shown to the user.
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Ini(err) => write!(f, "Couldn't read the config: {err}"),
+            Error::DeviceNotFound => f.write_str("No device found!"),
+            Error::InvalidDevice => f.write_str(
+                "Graphics device invalid.\nMake sure you have the correct and latest drivers.",
+            ),
+            Error::EmptyCommand => f.write_str("No command given."),
+            Error::InvalidArgument(msg)
+            | Error::EnvFile(msg)
+            | Error::NoMatchingGpu(msg)
+            | Error::Power(msg) => f.write_str(msg),
+            Error::EnvTemplate(msg) => {
+                write!(f, "Invalid value in the [env] config section: {msg}")
+            }
+            Error::InvalidConfig(msg) => write!(f, "Invalid config: {msg}"),
+            Error::VulkanUuidUnavailable => f.write_str(
+                "Couldn't resolve the Vulkan device UUID for this GPU.\nThis is currently only supported on NVIDIA cards with nvidia-smi installed.",
+            ),
+            Error::UnsupportedVendor(vendor) => write!(
+                f,
+                "GPU offload isn't implemented for discrete {vendor} GPUs yet."
+            ),
+            Error::DesktopEntry(msg) => write!(f, "Can't run desktop entry {msg}"),
+            Error::DBus(msg) => write!(f, "D-Bus: {msg}"),
+            Error::UnrecognizedDevices(devices) => write!(
+                f,
+                "Not guessing which GPU to use (--strict-vendor), primer doesn't recognize:\n{}",
+                devices.join("\n")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Ini(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<tini::Error> for Error {
+    fn from(err: tini::Error) -> Self {
+        Error::Ini(err)
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum Vendor {
+    NVIDIA,
+    AMD,
+    Intel,
+}
+
+impl Vendor {
+    /// The vendor owning a PCI vendor ID, e.g. `0x10de` for NVIDIA.
+    pub fn from_pci_vendor_id(id: u16) -> Option<Self> {
+        match id {
+            0x10de => Some(Vendor::NVIDIA),
+            0x1002 | 0x1022 => Some(Vendor::AMD),
+            0x8086 => Some(Vendor::Intel),
+            _ => None,
+        }
+    }
+    /// Parses a vendor name as written in the config, e.g. `nvidia`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "nvidia" => Some(Vendor::NVIDIA),
+            "amd" => Some(Vendor::AMD),
+            "intel" => Some(Vendor::Intel),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Vendor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Vendor::NVIDIA => "NVIDIA",
+            Vendor::AMD => "AMD",
+            Vendor::Intel => "Intel",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clocks {
+    pub core_mhz: u32,
+    pub memory_mhz: u32,
+}
+
+/// Finds the active state in an amdgpu `pp_dpm_*` file, which lists one `N: <clock>Mhz` per line
+/// and marks the current one with `*`.
+fn active_dpm_clock(states: &str) -> Option<u32> {
+    let line = states.lines().find(|line| line.trim_end().ends_with('*'))?;
+    let (_, clock) = line.split_once(':')?;
+    clock
+        .trim()
+        .trim_end_matches('*')
+        .trim()
+        .to_ascii_lowercase()
+        .trim_end_matches("mhz")
+        .parse()
+        .ok()
+}
+
+/// A graphics device primer can launch on. Everything primer needs is copied out of udev when
+/// the GPU is found, so it's plain data that can be built from any [`DeviceInfo`].
+#[derive(Debug, Clone)]
+pub struct GPU {
+    vendor: Vendor,
+    name: String,
+    integrated: bool,
+    driver: String,
+    pci_address: Option<String>,
+    pci_ids: Option<(u16, u16)>,
+    /// See [`GPU::sysfs_path`].
+    sysfs: PathBuf,
+    dev: DeviceInfo,
+}
+
+// the unplug and reset watching only needs a GPU's sysfs path and name, but a live udev handle
+// finding its way into GPU would keep it from ever moving that to another thread
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<GPU>();
+};
+
+impl GPU {
+    /// Recognizes a GPU by its kernel driver, or for display controllers with a driver primer
+    /// doesn't know by their PCI vendor ID. `None` for anything else.
+    pub fn from_device(dev: DeviceInfo) -> Option<Self> {
+        let driver = dev.driver.clone().unwrap_or_default();
+        let pci_ids = pci_ids(&dev);
+        let vendor = match driver.as_str() {
+            "nvidia" => Some(Vendor::NVIDIA),
+            "i915" => Some(Vendor::Intel),
+            "radv" | "radeon" => Some(Vendor::AMD),
+            // passed through to a VM, the host can't render on it
+            "vfio-pci" | "pci-stub" => None,
+            _ if is_display_controller(&dev) => {
+                let vendor = Vendor::from_pci_vendor_id(pci_ids?.0)?;
+                log::debug(format!(
+                    "Recognized {} as {vendor} by its PCI vendor ID, driver \"{driver}\"",
+                    dev.syspath.display()
+                ));
+                Some(vendor)
+            }
+            _ => None,
+        }?;
+        let name = match (dev.property("ID_MODEL_FROM_DATABASE"), pci_ids) {
+            (Some(name), _) if !name.is_empty() => name.to_string(),
+            // udev's hwdb can lag behind pci.ids, and without either the IDs still tell cards apart
+            (_, Some((vendor, device))) => pci_ids::device_name(vendor, device)
+                .map(String::from)
+                .unwrap_or_else(|| format!("{vendor:04x}:{device:04x}")),
+            (_, None) => String::new(),
+        };
+        let pci_address = dev.property("PCI_SLOT_NAME").map(String::from);
+        // add-in cards always sit behind a bridge, so a GPU on the root bus is part of the CPU
+        // or chipset; AMD puts its APU graphics behind an internal bridge, hence the name check
+        let integrated = pci_address.as_deref().is_some_and(on_root_bus)
+            || name.to_lowercase().contains("integrated");
+        Some(GPU {
+            vendor,
+            name,
+            integrated,
+            driver,
+            sysfs: if dev.syspath.as_os_str().is_empty() {
+                sysfs_root()
+                    .join("bus/pci/devices")
+                    .join(pci_address.as_deref().unwrap_or_default())
+            } else {
+                dev.syspath.clone()
+            },
+            pci_address,
+            pci_ids,
+            dev,
+        })
+    }
+    pub fn vendor(&self) -> &Vendor {
+        &self.vendor
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn integrated(&self) -> bool {
+        self.integrated
+    }
+    /// The PCI vendor ID, e.g. `0x10de` for NVIDIA.
+    pub fn vendor_id(&self) -> Option<u16> {
+        self.pci_ids.map(|(vendor, _)| vendor)
+    }
+    /// The PCI device ID, identifying the model.
+    pub fn device_id(&self) -> Option<u16> {
+        self.pci_ids.map(|(_, device)| device)
+    }
+    /// The kernel driver bound to the device, e.g. `nvidia`.
+    pub fn driver(&self) -> &str {
+        &self.driver
+    }
+    /// The device's sysfs directory, which every sensor and power accessor reads below. It's
+    /// the path udev reported, or `bus/pci/devices/<address>` below [`sysfs_root`] for sources
+    /// that don't report one, so a [`DeviceSource`] can point GPUs at a fake sysfs tree.
+    pub fn sysfs_path(&self) -> &Path {
+        &self.sysfs
+    }
+    /// Everything udev reported about the device when it was found.
+    pub fn device(&self) -> &DeviceInfo {
+        &self.dev
+    }
+    pub fn name_fancy(&self, colors: &config::Colors) -> ColoredString {
+        self.name.color(colors.get(&self.vendor))
+    }
+    pub fn print_info(&self, colors: &config::Colors) {
+        let name = format!("-- {} --", self.name_fancy(colors)).bold();
+        println!("{}", name);
+        for (label, value) in self.info() {
+            println!("{}: {}", label.bold(), value);
+        }
+    }
+    /// What `--info` prints about this GPU: its udev properties, then topology, clocks and
+    /// what Vulkan reports for it.
+    pub fn info(&self) -> Vec<(String, String)> {
+        let mut info: Vec<(String, String)> = self
+            .dev
+            .properties
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        for (label, value) in self.topology() {
+            info.push((label.to_string(), value));
+        }
+        if let Some(clocks) = self.clocks() {
+            info.push((
+                String::from("clocks"),
+                format!(
+                    "{} MHz core, {} MHz memory",
+                    clocks.core_mhz, clocks.memory_mhz
+                ),
+            ));
+        }
+        if let Some(vulkan) = self.vulkan_info() {
+            info.push((String::from("vulkan device"), vulkan.device_name));
+            info.push((String::from("vulkan api"), vulkan.api_version));
+        }
+        info
+    }
+    pub fn pci_slot(&self) -> Option<String> {
+        self.pci_address().map(|slot| {
+            slot.chars()
+                .map(|c| match c {
+                    ':' | '.' => '_',
+                    _ => c,
+                })
+                .collect()
+        })
+    }
+    /// The un-mangled PCI address, e.g. `0000:01:00.0`.
+    pub fn pci_address(&self) -> Option<&str> {
+        self.pci_address.as_deref()
+    }
+    /// Where the GPU sits on the PCIe bus, for working out how a hybrid laptop is wired.
+    pub fn topology(&self) -> Vec<(&'static str, String)> {
+        let mut topology = Vec::new();
+        if let Some(path) = self.dev.property("ID_PATH") {
+            topology.push(("path", path.to_string()));
+        }
+        if let Some(parent) = &self.dev.parent {
+            let mut bus = format!(
+                "{} {}",
+                parent.subsystem.as_deref().unwrap_or("unknown"),
+                parent.sysname
+            );
+            if let Some(driver) = &parent.driver {
+                bus.push_str(&format!(" ({driver})"));
+            }
+            topology.push(("parent", bus));
+        }
+        topology
+    }
+    /// The `hwmon*` sensor directories of this GPU. Some drivers register more than one.
+    fn hwmon_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = std::fs::read_dir(self.sysfs.join("hwmon"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect();
+        dirs.sort();
+        dirs
+    }
+    /// Reads the first hwmon sensor file called `name` that holds a number.
+    fn hwmon_value(&self, name: &str) -> Option<u64> {
+        self.hwmon_dirs().iter().find_map(|dir| {
+            std::fs::read_to_string(dir.join(name))
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+        })
+    }
+    /// Fan speed in RPM. `None` on fanless cards or when the driver doesn't report it.
+    pub fn fan_speed(&self) -> Option<u32> {
+        self.hwmon_value("fan1_input")
+            .and_then(|rpm| u32::try_from(rpm).ok())
+    }
+    pub fn to_json(&self, index: Option<usize>) -> json::Object {
+        json::Object::new()
+            .field("index", index)
+            .field("vendor", self.vendor.to_string())
+            .field("name", &self.name)
+            .field("pci", self.pci_slot())
+            .field("integrated", self.integrated)
+            .field("driver", &self.driver)
+            .field(
+                "pci_id",
+                self.pci_ids
+                    .map(|(vendor, device)| format!("{vendor:04x}:{device:04x}")),
+            )
+            .field("fan_speed", self.fan_speed())
+            .field(
+                "clocks",
+                self.clocks().map(|clocks| {
+                    json::Object::new()
+                        .field("core_mhz", clocks.core_mhz)
+                        .field("memory_mhz", clocks.memory_mhz)
+                }),
+            )
+    }
+    /// The `/dev/dri/renderD*` node belonging to this GPU.
+    pub fn render_node(&self) -> Option<PathBuf> {
+        std::fs::read_dir(self.sysfs.join("drm"))
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .find(|name| name.to_string_lossy().starts_with("renderD"))
+            .map(|name| PathBuf::from("/dev/dri").join(name))
+    }
+    /// The `/dev/dri/card*` node belonging to this GPU.
+    pub fn card_path(&self) -> Option<PathBuf> {
+        std::fs::read_dir(self.sysfs.join("drm"))
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .find(|name| {
+                name.to_str()
+                    .and_then(|name| name.strip_prefix("card"))
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .map(|name| PathBuf::from("/dev/dri").join(name))
+    }
+    /// The card's display connectors by sysfs name (e.g. `DP-1`), each with whether a display
+    /// is connected. Empty for GPUs without outputs, like render-only or compute cards.
+    pub fn connectors(&self) -> Vec<(String, bool)> {
+        let card = match self
+            .card_path()
+            .and_then(|path| path.file_name().map(PathBuf::from))
+        {
+            Some(card) => self.sysfs.join("drm").join(card),
+            None => return Vec::new(),
+        };
+        let entries = match std::fs::read_dir(&card) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut connectors: Vec<(String, bool)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let (_, name) = file_name.to_str()?.strip_prefix("card")?.split_once('-')?;
+                let status = std::fs::read_to_string(entry.path().join("status")).ok()?;
+                Some((name.to_string(), status.trim() == "connected"))
+            })
+            .collect();
+        connectors.sort();
+        connectors
+    }
+    /// Whether a display is connected to one of the card's outputs.
+    pub fn drives_display(&self) -> bool {
+        self.connectors().iter().any(|(_, connected)| *connected)
+    }
+    /// N of the `/dev/dri/cardN` node.
+    pub fn card_index(&self) -> Option<u32> {
+        self.card_path()?
+            .file_name()?
+            .to_str()?
+            .strip_prefix("card")?
+            .parse()
+            .ok()
+    }
+    /// The runtime PM state from `power/runtime_status`, e.g. `active` or `suspended`.
+    pub fn runtime_status(&self) -> Option<String> {
+        std::fs::read_to_string(self.sysfs.join("power/runtime_status"))
+            .ok()
+            .map(|status| status.trim().to_string())
+    }
+    /// Writes `power/control`: `auto` lets the kernel suspend the GPU while it's idle, `on`
+    /// keeps it powered.
+    pub fn set_power_control(&self, value: &str) -> Result<(), Error> {
+        let path = self.sysfs.join("power/control");
+        std::fs::write(&path, value).map_err(|err| match err.kind() {
+            std::io::ErrorKind::PermissionDenied => Error::Power(format!(
+                "Not allowed to write {}, try again as root",
+                path.display()
+            )),
+            _ => Error::Power(format!("Couldn't write {}: {err}", path.display())),
+        })
+    }
+    /// Wakes the GPU from runtime suspend by opening its render node, waiting up to `timeout`
+    /// for it to report `active`. Returns whether it had to be woken; GPUs that are already
+    /// awake or don't report a power state are left as they are.
+    pub fn wake(&self, timeout: std::time::Duration) -> Result<bool, Error> {
+        match self.runtime_status().as_deref() {
+            None | Some("active") => return Ok(false),
+            Some(_) => (),
+        }
+        let node = self.render_node().ok_or(Error::InvalidDevice)?;
+        // the device stays resumed while it's open, so keep it open until it says so
+        let _file = std::fs::File::open(&node)?;
+        let deadline = Instant::now() + timeout;
+        while self.runtime_status().as_deref() != Some("active") && Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        Ok(true)
+    }
+    /// Processes that have this GPU's card or render node open.
+    pub fn users(&self) -> Vec<u32> {
+        let nodes: Vec<PathBuf> = [self.card_path(), self.render_node()]
+            .into_iter()
+            .flatten()
+            .collect();
+        let procs = match std::fs::read_dir(proc_root()) {
+            Ok(procs) => procs,
+            Err(_) => return Vec::new(),
+        };
+        procs
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter(|pid| {
+                std::fs::read_dir(proc_root().join(pid.to_string()).join("fd"))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+                    .any(|target| nodes.contains(&target))
+            })
+            .collect()
+    }
+    /// Value for a `{placeholder}` in config env values.
+    fn placeholder(&self, name: &str) -> Option<String> {
+        match name {
+            "pci" => self.pci_slot(),
+            "vendor" => Some(self.vendor.to_string()),
+            "name" => Some(self.name.clone()),
+            "render_node" => self
+                .render_node()
+                .map(|node| node.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+    /// How the Vulkan driver sees this GPU, looked up by PCI IDs in `vulkaninfo --summary`.
+    /// `None` when vulkaninfo isn't installed or doesn't list the card.
+    pub fn vulkan_info(&self) -> Option<vulkan::VulkanInfo> {
+        let (vendor_id, device_id) = self.pci_ids?;
+        vulkan::summary()?
+            .iter()
+            .find(|info| info.vendor_id == vendor_id && info.device_id == device_id)
+            .cloned()
+    }
+    /// The Vulkan `deviceUUID` of this GPU, where it can be resolved without a Vulkan instance.
+    pub fn vulkan_uuid(&self) -> Option<String> {
+        match self.vendor {
+            // the NVIDIA driver reports the same UUID to Vulkan as to nvidia-smi
+            Vendor::NVIDIA => {
+                let uuid = self.nvidia_smi("uuid")?;
+                Some(uuid.strip_prefix("GPU-").unwrap_or(&uuid).to_string())
+            }
+            Vendor::AMD | Vendor::Intel => None,
+        }
+    }
+    /// Queries `nvidia-smi` about this GPU, e.g. `query = "uuid"`. Multiple comma separated
+    /// fields come back comma separated, without units.
+    fn nvidia_smi(&self, query: &str) -> Option<String> {
+        let output = Command::new("nvidia-smi")
+            .arg(format!("--query-gpu={query}"))
+            .args(["--format=csv,noheader,nounits", "-i"])
+            .arg(self.pci_address()?)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let value = String::from_utf8(output.stdout).ok()?;
+        Some(value.trim().to_string()).filter(|value| !value.is_empty())
+    }
+    /// Dedicated video memory in bytes, from amdgpu's `mem_info_vram_total`. Other drivers
+    /// don't say in sysfs.
+    pub fn vram_total(&self) -> Option<u64> {
+        std::fs::read_to_string(self.sysfs.join("mem_info_vram_total"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+    /// Current core and memory clocks, from sysfs on AMD and `nvidia-smi` on NVIDIA.
+    pub fn clocks(&self) -> Option<Clocks> {
+        match self.vendor {
+            Vendor::AMD => {
+                let active = |file: &str| {
+                    std::fs::read_to_string(self.sysfs.join(file))
+                        .ok()
+                        .and_then(|states| active_dpm_clock(&states))
+                };
+                Some(Clocks {
+                    core_mhz: active("pp_dpm_sclk")?,
+                    memory_mhz: active("pp_dpm_mclk")?,
+                })
+            }
+            Vendor::NVIDIA => {
+                let clocks = self.nvidia_smi("clocks.sm,clocks.mem")?;
+                let (core, memory) = clocks.split_once(',')?;
+                Some(Clocks {
+                    core_mhz: core.trim().parse().ok()?,
+                    memory_mhz: memory.trim().parse().ok()?,
+                })
+            }
+            Vendor::Intel => None,
+        }
+    }
+    /// What `DRI_PRIME` is set to for offloading to this GPU.
+    pub fn dri_prime_value(&self, format: config::DriPrimeFormat) -> Result<String, Error> {
+        match format {
+            config::DriPrimeFormat::Pci => self.pci_slot().map(|pci| format!("pci-{pci}")),
+            config::DriPrimeFormat::PciAddress => {
+                self.pci_address().map(|address| format!("pci-{address}"))
+            }
+            config::DriPrimeFormat::Index => self.card_index().map(|card| card.to_string()),
+        }
+        .ok_or(Error::InvalidDevice)
+    }
+    /// Every variable primer sets for a launch on this GPU, in the order they apply.
+    pub fn launch_env(
+        &self,
+        env: &[(String, String)],
+        config: &config::Config,
+    ) -> Result<env::EnvVars, Error> {
+        let pci = match self.pci_slot() {
+            Some(pci) => pci,
+            None => return Err(Error::InvalidDevice),
+        };
+        let mut vars = env::EnvVars::new();
+        match self.vendor {
+            Vendor::NVIDIA => {
+                env::set(
+                    &mut vars,
+                    "DRI_PRIME",
+                    self.dri_prime_value(config.dri_prime_format)?,
+                );
+                // these select NVIDIA's own GL and Vulkan drivers, which only run on its
+                // kernel driver and not on, say, nouveau
+                if self.driver == "nvidia" {
+                    if let Some(layer) = &config.nvidia_vk_optimus {
+                        env::set(&mut vars, "__VK_LAYER_NV_optimus", layer);
+                    }
+                    env::set(&mut vars, "__GLX_VENDOR_LIBRARY_NAME", "nvidia");
+                }
+            }
+            Vendor::AMD => {
+                env::set(
+                    &mut vars,
+                    "DRI_PRIME",
+                    self.dri_prime_value(config.dri_prime_format)?,
+                );
+            }
+            // reverse PRIME: the displays hang off another GPU, which would render by default
+            Vendor::Intel if self.integrated && config.reverse_prime && !self.drives_display() => {
+                env::set(
+                    &mut vars,
+                    "DRI_PRIME",
+                    self.dri_prime_value(config.dri_prime_format)?,
+                );
+            }
+            // integrated graphics render by default, nothing to offload
+            Vendor::Intel if self.integrated => (),
+            // arc cards not supported yet
+            Vendor::Intel => return Err(Error::UnsupportedVendor(self.vendor.clone())),
+        };
+        vars.retain(|(key, _)| !config.unset_env.contains(key));
+        env::warn_if_inherited(&vars);
+        env::set(&mut vars, "PRIMER_GPU", &self.name);
+        env::set(&mut vars, "PRIMER_GPU_PCI", &pci);
+        if config.vulkan_uuid {
+            let uuid = self.vulkan_uuid().ok_or(Error::VulkanUuidUnavailable)?;
+            env::set(&mut vars, "PRIMER_VK_DEVICE_UUID", uuid);
+        }
+        if config.wgpu_hints {
+            // wgpu matches the adapter name against what Vulkan reports, which is usually
+            // closer to the marketing name than udev's
+            let adapter = match self.vulkan_info() {
+                Some(info) => info.device_name,
+                None => self.name.clone(),
+            };
+            env::set(&mut vars, "WGPU_BACKEND", "vulkan");
+            env::set(&mut vars, "WGPU_ADAPTER_NAME", adapter);
+        }
+        let preset = match &config.preset {
+            Some(name) => config.preset_env(name)?,
+            None => env::EnvVars::new(),
+        };
+        for (key, value) in config.env.iter().chain(&preset) {
+            env::warn_if_reserved(key);
+            let value = env::expand(value, |name| self.placeholder(name))?;
+            env::set(&mut vars, key, value);
+        }
+        // user overrides go last so they win over the vendor defaults
+        for (key, value) in env {
+            env::warn_if_reserved(key);
+            env::set(&mut vars, key, value);
+        }
+        Ok(vars)
+    }
+    /// `launch_env` with the `append_env` variables joined onto their inherited values.
+    fn joined_env(
+        &self,
+        env: &[(String, String)],
+        config: &config::Config,
+    ) -> Result<env::EnvVars, Error> {
+        let mut vars = self.launch_env(env, config)?;
+        env::prepend_inherited(&mut vars, &config.append_env);
+        Ok(vars)
+    }
+    /// The variables `prepare_run` would set, without any `--env` overrides, for callers that
+    /// apply the environment themselves.
+    pub fn offload_env(&self, config: &config::Config) -> Result<HashMap<String, String>, Error> {
+        Ok(self.joined_env(&[], config)?.into_iter().collect())
+    }
+    /// [`GPU::prepare_command`] for a command line whose first item is the program.
+    pub fn prepare_run(
+        &self,
+        command: &[String],
+        env: &[(String, String)],
+        config: &config::Config,
+    ) -> Result<Command, Error> {
+        let (program, args) = command.split_first().ok_or(Error::EmptyCommand)?;
+        self.prepare_command(program, args, env, config)
+    }
+    /// A `Command` that runs `program` with `args` on this GPU, with `env` on top of the
+    /// launch env. Says which GPU is used unless primer is quiet.
+    pub fn prepare_command(
+        &self,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+        env: &[(String, String)],
+        config: &config::Config,
+    ) -> Result<Command, Error> {
+        if log::level() > log::Level::Quiet {
+            println!(
+                "{}",
+                format!("-- Using GPU: {} --", self.name_fancy(&config.colors)).bold()
+            );
+        }
+        self.build_command(program, args, env, config)
+    }
+    /// [`GPU::prepare_command`] without the announcement, for callers that name the GPU
+    /// themselves.
+    pub fn build_command(
+        &self,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+        env: &[(String, String)],
+        config: &config::Config,
+    ) -> Result<Command, Error> {
+        let vars = self.joined_env(env, config)?;
+        let mut cmd = std::process::Command::new(program);
+        cmd.args(args);
+        filter_inherited_env(&mut cmd, config);
+        cmd.envs(vars);
+        if let Some(level) = config.nice {
+            process::set_nice(&mut cmd, level);
+        }
+        if let Some(priority) = config.ionice {
+            process::set_io_priority(&mut cmd, priority);
+        }
+        if config.render_fd {
+            let node = self.render_node().ok_or(Error::InvalidDevice)?;
+            let file = std::fs::File::options()
+                .read(true)
+                .write(true)
+                .open(&node)?;
+            cmd.env("PRIMER_RENDER_FD", file.as_raw_fd().to_string());
+            cmd.env("PRIMER_RENDER_NODE", &node);
+            // the closure owns the file so it stays open until the spawn, and clears
+            // close-on-exec in the child only, so primer itself doesn't leak it elsewhere
+            unsafe {
+                cmd.pre_exec(move || {
+                    if libc::fcntl(file.as_raw_fd(), libc::F_SETFD, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        Ok(cmd)
+    }
+    /// Writes an executable shell script that exports this GPU's launch env and runs `"$@"`.
+    pub fn write_wrapper(
+        &self,
+        path: &Path,
+        overwrite: bool,
+        env: &[(String, String)],
+        config: &config::Config,
+    ) -> Result<(), Error> {
+        let vars = self.launch_env(env, config)?;
+        let mut script = format!(
+            "#!/bin/sh\n# Generated by primer for {} ({})\n",
+            self.name,
+            self.pci_slot().unwrap_or_default()
+        );
+        for (key, value) in &vars {
+            // append_env keys are joined when the script runs, not when it's written
+            let inherited = if config.append_env.contains(key) {
+                format!("\"${{{key}:+{}${key}}}\"", env::append_separator(key))
+            } else {
+                String::new()
+            };
+            script.push_str(&format!(
+                "export {key}={}{inherited}\n",
+                env::shell_quote(value)
+            ));
+        }
+        script.push_str("exec \"$@\"\n");
+
+        let mut file = std::fs::OpenOptions::new();
+        file.write(true).mode(0o755);
+        if overwrite {
+            file.create(true).truncate(true);
+        } else {
+            file.create_new(true);
+        }
+        let mut file = file.open(path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::AlreadyExists => Error::InvalidArgument(format!(
+                "\"{}\" already exists, pass --force to overwrite it",
+                path.display()
+            )),
+            _ => Error::Io(err),
+        })?;
+        file.write_all(script.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Every GPU `source` knows about. The scan itself happens up front, but devices are only
+/// recognized as they're pulled from the iterator, and sensors like [`GPU::fan_speed`] are
+/// read when asked for, so callers looking for one GPU can stop at the first match.
+pub fn find_gpus_iter(
+    source: &(impl DeviceSource + ?Sized),
+) -> Result<impl Iterator<Item = GPU>, Error> {
+    Ok(recognize(source.scan()?))
+}
+
+pub fn find_gpus(source: &(impl DeviceSource + ?Sized)) -> Result<Vec<GPU>, Error> {
+    let scanned = source.scan()?;
+    let others = scanned
+        .iter()
+        .filter(|dev| !is_display_controller(dev))
+        .count();
+    let mut devices: Vec<GPU> = recognize(scanned).collect();
+    // one PCI function is one GPU, a second entry for it means it was recognized twice and
+    // selection could pick either
+    let mut seen: Vec<(String, String)> = Vec::new();
+    devices.retain(|gpu| {
+        let Some(slot) = gpu.pci_slot() else {
+            return true;
+        };
+        if let Some((_, first)) = seen.iter().find(|(seen, _)| *seen == slot) {
+            log::warn(format!(
+                "{} and {} are both at PCI slot {slot}, this is a bug in primer. Only {first} is used.",
+                first, gpu.name
+            ));
+            return false;
+        }
+        seen.push((slot, gpu.name.clone()));
+        true
+    });
+    let skipped = others
+        - devices
+            .iter()
+            .filter(|gpu| !is_display_controller(&gpu.dev))
+            .count();
+    log::debug(format!(
+        "Skipped {skipped} devices that aren't display controllers"
+    ));
+    if !devices.is_empty() {
+        Ok(devices)
+    } else {
+        Err(Error::DeviceNotFound)
+    }
+}
+
+/// For `--strict-vendor`: fails if a display controller is neither recognized by its driver
+/// nor by its PCI vendor ID. Cards passed through to a VM are known to be unusable, so they
+/// don't count.
+pub fn check_recognized(source: &(impl DeviceSource + ?Sized)) -> Result<(), Error> {
+    let unrecognized: Vec<String> = source
+        .scan()?
+        .into_iter()
+        .filter(is_display_controller)
+        .filter(|dev| !matches!(dev.driver.as_deref(), Some("vfio-pci" | "pci-stub")))
+        .filter(|dev| GPU::from_device(dev.clone()).is_none())
+        .map(|dev| {
+            let id = pci_ids(&dev)
+                .map(|(vendor, device)| format!("{vendor:04x}:{device:04x}"))
+                .unwrap_or_else(|| String::from("unknown ID"));
+            format!(
+                "{} ({id}, driver \"{}\")",
+                dev.property("PCI_SLOT_NAME")
+                    .map(String::from)
+                    .unwrap_or_else(|| dev.syspath.display().to_string()),
+                dev.driver.as_deref().unwrap_or("none")
+            )
+        })
+        .collect();
+    if unrecognized.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::UnrecognizedDevices(unrecognized))
+    }
+}
+
+/// The GPUs among `devices`, recognized one at a time as the iterator is advanced.
+fn recognize(devices: Vec<DeviceInfo>) -> impl Iterator<Item = GPU> {
+    devices.into_iter().filter_map(|dev| {
+        // only display controllers get a reason logged, there are hundreds of other devices
+        let reason = is_display_controller(&dev).then(|| {
+            format!(
+                "Skipping display controller {}: neither its driver \"{}\" nor its PCI ID is one primer recognizes",
+                dev.syspath.display(),
+                dev.driver.as_deref().unwrap_or("none")
+            )
+        });
+        let gpu = GPU::from_device(dev);
+        if let (None, Some(reason)) = (&gpu, reason) {
+            log::debug(reason);
+        }
+        gpu
+    })
+}
+
+/// Whether a PCI address like `0000:00:02.0` is on bus 0, where the root complex is.
+fn on_root_bus(address: &str) -> bool {
+    let mut parts = address.split(':');
+    // the domain is optional in some udev versions
+    let bus = match (parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(bus), Some(_)) => bus,
+        (Some(bus), Some(_), None) => bus,
+        _ => return false,
+    };
+    u8::from_str_radix(bus, 16) == Ok(0)
+}
+
+/// `--list-drivers`: the kernel driver of every display controller and GPU, whether primer
+/// recognizes it, and the userspace GL and Vulkan drivers that go with it. One tab separated
+/// line each, so it can be pasted into bug reports as is.
+fn print_drivers(source: &(impl DeviceSource + ?Sized)) -> Result<i32, Error> {
+    for line in driver_lines(source)? {
+        println!("{line}");
+    }
+    Ok(0)
+}
+
+/// The lines `--list-drivers` prints, header first.
+fn driver_lines(source: &(impl DeviceSource + ?Sized)) -> Result<Vec<String>, Error> {
+    let mut lines = vec![String::from("pci\tid\tkernel\tvendor\tgl\tvulkan")];
+    for dev in source.scan()? {
+        let gpu = GPU::from_device(dev.clone());
+        if !is_display_controller(&dev) && gpu.is_none() {
+            continue;
+        }
+        let driver = dev.driver.clone().unwrap_or_else(|| String::from("none"));
+        let (gl, vulkan) = match driver.as_str() {
+            "nvidia" => ("nvidia", "nvidia"),
+            "amdgpu" => ("radeonsi", "radv"),
+            "radeon" => ("r600/radeonsi", "-"),
+            "i915" | "xe" => ("iris", "anv"),
+            "nouveau" => ("nouveau", "nvk"),
+            _ => ("-", "-"),
+        };
+        let pci = dev.property("PCI_SLOT_NAME").unwrap_or("-").to_string();
+        let id = pci_ids(&dev)
+            .map(|(vendor, device)| format!("{vendor:04x}:{device:04x}"))
+            .unwrap_or_else(|| String::from("-"));
+        let vendor = match gpu {
+            Some(gpu) => gpu.vendor.to_string(),
+            None => String::from("not recognized"),
+        };
+        lines.push(format!("{pci}\t{id}\t{driver}\t{vendor}\t{gl}\t{vulkan}"));
+    }
+    Ok(lines)
+}
+
+/// Whether udev reports PCI base class 0x03 (display controller) for the device.
+fn is_display_controller(dev: &DeviceInfo) -> bool {
+    dev.property("PCI_CLASS")
+        .and_then(|class| u32::from_str_radix(class, 16).ok())
+        .is_some_and(|class| class >> 16 == 0x03)
+}
+
+/// PCI vendor and device ID, from `PCI_ID=10DE:2484` or udev's `ID_VENDOR_ID`/`ID_MODEL_ID`.
+fn pci_ids(dev: &DeviceInfo) -> Option<(u16, u16)> {
+    let parse = |id: &str| u16::from_str_radix(id.trim().trim_start_matches("0x"), 16).ok();
+    if let Some((vendor, device)) = dev.property("PCI_ID").and_then(|id| id.split_once(':')) {
+        return Some((parse(vendor)?, parse(device)?));
+    }
+    Some((
+        parse(dev.property("ID_VENDOR_ID")?)?,
+        parse(dev.property("ID_MODEL_ID")?)?,
+    ))
+}
+
+/// Whether the machine runs on battery: every mains supply in `/sys/class/power_supply` is
+/// offline. `None` without a mains supply to ask, e.g. on desktops.
+fn on_battery() -> Option<bool> {
+    let mut online = None;
+    for entry in std::fs::read_dir(sysfs_root().join("class/power_supply"))
+        .ok()?
+        .flatten()
+    {
+        let path = entry.path();
+        let kind = std::fs::read_to_string(path.join("type")).unwrap_or_default();
+        if kind.trim() != "Mains" {
+            continue;
+        }
+        if let Ok(value) = std::fs::read_to_string(path.join("online")) {
+            *online.get_or_insert(false) |= value.trim() == "1";
+        }
+    }
+    online.map(|online| !online)
+}
+
+/// How a hybrid laptop's internal panel is wired, see [`laptop_mux`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxKind {
+    /// The integrated GPU drives the panel and discrete GPUs render through it, which is what
+    /// offloading is for. Muxed laptops switched to integrated graphics look the same.
+    Muxless,
+    /// A hardware mux (Apple's gmux) routes the panel to the integrated GPU.
+    Integrated,
+    /// The discrete GPU at this PCI address drives the panel, through a mux set to it or
+    /// because it's wired that way.
+    Discrete(String),
+}
+
+/// Best-effort guess at how the panel is wired among `gpus`: vgaswitcheroo's view when debugfs
+/// is readable, otherwise which GPU has the connected eDP, LVDS or DSI connector. `None` when
+/// there's no panel to go by, e.g. on desktops.
+pub fn laptop_mux(gpus: &[GPU]) -> Option<MuxKind> {
+    let switch = sysfs_root().join("kernel/debug/vgaswitcheroo/switch");
+    if let Ok(clients) = std::fs::read_to_string(&switch) {
+        // lines like `1:DIS:+:Pwr:0000:01:00.0`, `+` marking the GPU that drives the panel
+        let active = clients.lines().find_map(|line| {
+            let fields: Vec<&str> = line.splitn(5, ':').collect();
+            match fields[..] {
+                [_, "DIS", "+", _, address] => Some(address.to_string()),
+                _ => None,
+            }
+        });
+        if let Some(address) = active {
+            log::debug(format!("vgaswitcheroo has {address} driving the panel"));
+            return Some(MuxKind::Discrete(address));
+        }
+        log::debug("vgaswitcheroo has the integrated GPU driving the panel");
+    }
+    let panel = |gpu: &&GPU| {
+        gpu.connectors().iter().any(|(name, connected)| {
+            *connected
+                && ["eDP", "LVDS", "DSI"]
+                    .iter()
+                    .any(|kind| name.starts_with(kind))
+        })
+    };
+    if let Some(gpu) = gpus.iter().filter(|gpu| !gpu.integrated).find(panel) {
+        log::debug(format!("The panel is connected to {}", gpu.name));
+        return gpu
+            .pci_address()
+            .map(|address| MuxKind::Discrete(address.to_string()));
+    }
+    let gmux = std::fs::read_dir(sysfs_root().join("bus/acpi/devices"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .any(|entry| entry.file_name().to_string_lossy().starts_with("APP000B"));
+    if gmux {
+        log::debug("Found Apple's gmux, set to integrated graphics");
+        return Some(MuxKind::Integrated);
+    }
+    let integrated = gpus.iter().filter(|gpu| gpu.integrated).find(panel)?;
+    log::debug(format!(
+        "The panel is connected to {}, no mux found",
+        integrated.name
+    ));
+    Some(MuxKind::Muxless)
+}
+
+/// The variables that make drivers render on another GPU, which `--igpu` leaves out.
+const OFFLOAD_VARS: &[&str] = &[
+    "DRI_PRIME",
+    "__VK_LAYER_NV_optimus",
+    "__GLX_VENDOR_LIBRARY_NAME",
+];
+
+/// Makes launches with `config` plain ones, as if primer wasn't there, by leaving out the
+/// [`OFFLOAD_VARS`].
+pub fn skip_offload(config: &mut config::Config) {
+    config
+        .unset_env
+        .extend(OFFLOAD_VARS.iter().map(|var| var.to_string()));
+}
+
+/// Sort key for GPU selection, lower wins: `default_vendor` first, then `gpu_priority`.
+/// Vendors `gpu_priority` doesn't list come after every listed one, with a `None` priority.
+pub fn rank(config: &config::Config, gpu: &GPU) -> (bool, bool, Option<usize>) {
+    let not_default = config
+        .default_vendor
+        .as_ref()
+        .is_some_and(|vendor| vendor != &gpu.vendor);
+    let priority = match &config.gpu_priority {
+        config::GpuPriority::Auto => Some(gpu.integrated as usize),
+        config::GpuPriority::Vendors(vendors) => vendors.iter().position(|p| p == &gpu.vendor),
+        // higher scores win, so they're turned around to sort first
+        config::GpuPriority::Weights(weights) => {
+            weighted_score(weights, gpu).map(|score| (u32::MAX - score) as usize)
+        }
+    };
+    (not_default, priority.is_none(), priority)
+}
+
+/// Puts `gpus` in the order [`select_gpu`] expects, best ranked first and ties by PCI slot.
+pub fn sort_by_rank(gpus: &mut [GPU], config: &config::Config) {
+    // ranks and slots are computed once per GPU rather than once per comparison
+    gpus.sort_by_cached_key(|gpu| (rank(config, gpu), gpu.pci_slot()));
+}
+
+/// The score of `gpu` under weighted `gpu_priority`: its vendor's weight, plus 1 for a
+/// discrete GPU and 1 per 8 GiB of VRAM. `None` for vendors without a weight.
+pub fn weighted_score(weights: &[(Vendor, u32)], gpu: &GPU) -> Option<u32> {
+    let (_, weight) = weights.iter().find(|(vendor, _)| vendor == &gpu.vendor)?;
+    let discrete = u32::from(!gpu.integrated);
+    let vram = gpu.vram_total().map_or(0, |bytes| (bytes >> 33) as u32);
+    Some(weight.saturating_add(discrete).saturating_add(vram))
+}
+
+/// Prints how the GPUs ranked for `--explain`, best first, and why `chosen` was used.
+fn explain(
+    config: &config::Config,
+    gpus: &[GPU],
+    chosen: &GPU,
+    battery_saving: bool,
+    reason: &str,
+) {
+    let mut table = table::Table::new();
+    table.row(
+        ["", "GPU", "DEFAULT_VENDOR", "GPU_PRIORITY", "PCI"]
+            .iter()
+            .map(|header| header.bold().to_string())
+            .collect(),
+    );
+    for gpu in gpus {
+        let (not_default, _, priority) = rank(config, gpu);
+        let default = match &config.default_vendor {
+            Some(_) if not_default => String::from("no"),
+            Some(_) => String::from("yes"),
+            None => String::from("-"),
+        };
+        let priority = match (&config.gpu_priority, priority) {
+            (config::GpuPriority::Auto, _) if gpu.integrated => String::from("auto: integrated"),
+            (config::GpuPriority::Auto, _) => String::from("auto: discrete"),
+            (config::GpuPriority::Weights(weights), Some(_)) => format!(
+                "score {} ({})",
+                weighted_score(weights, gpu).unwrap_or_default(),
+                gpu.vendor
+            ),
+            (_, Some(position)) => format!("{} ({})", position + 1, gpu.vendor),
+            (_, None) => format!("not listed ({})", gpu.vendor),
+        };
+        let marker = if std::ptr::eq(gpu, chosen) { "*" } else { "" };
+        table.row(vec![
+            marker.to_string(),
+            gpu.name_fancy(&config.colors).to_string(),
+            default,
+            priority,
+            gpu.pci_slot().unwrap_or_default(),
+        ]);
+    }
+    println!("{}", "-- Selection --".bold());
+    for line in table.lines() {
+        println!("{line}");
+    }
+    println!("GPUs rank by default_vendor, then gpu_priority, ties go to the lower PCI slot.");
+    if battery_saving {
+        println!(
+            "Integrated graphics come first because the machine runs on battery (power_aware)."
+        );
+    }
+    println!(
+        "Using {} because {reason}.",
+        chosen.name_fancy(&config.colors).bold()
+    );
+}
+
+/// Fails unless `gpu` is the configured first choice: `default_vendor`, or else the head of
+/// `gpu_priority` (any discrete GPU for `auto`).
+fn check_preferred(config: &config::Config, gpu: &GPU) -> Result<(), Error> {
+    let wanted = match (&config.default_vendor, &config.gpu_priority) {
+        (Some(vendor), _) => Some(vendor),
+        (None, config::GpuPriority::Vendors(vendors)) => vendors.first(),
+        // with weights any listed discrete GPU can win on its score
+        (None, config::GpuPriority::Auto | config::GpuPriority::Weights(_)) => {
+            if gpu.integrated {
+                return Err(Error::NoMatchingGpu(String::from(
+                    "No discrete GPU found, not falling back to integrated graphics (--no-fallback)",
+                )));
+            }
+            return Ok(());
+        }
+    };
+    match wanted {
+        Some(vendor) if vendor != &gpu.vendor => Err(Error::NoMatchingGpu(format!(
+            "No {vendor} GPU found, not falling back to {} (--no-fallback)",
+            gpu.name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Asks the user to choose between equally ranked GPUs. An empty answer picks the first one.
+fn pick_gpu<'a>(gpus: &[&'a GPU], colors: &config::Colors) -> Result<&'a GPU, Error> {
+    println!("{}", "-- Multiple GPUs match, pick one --".bold());
+    for (i, gpu) in gpus.iter().enumerate() {
+        println!(
+            "{}) {} {} ({})",
+            i,
+            gpu.vendor,
+            gpu.name_fancy(colors),
+            gpu.pci_slot().unwrap_or_default()
+        );
+    }
+    let stdin = std::io::stdin();
+    loop {
+        print!("GPU [0]: ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        if stdin.read_line(&mut answer)? == 0 {
+            return Ok(gpus[0]);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(gpus[0]);
+        }
+        match answer.parse::<usize>().ok().and_then(|i| gpus.get(i)) {
+            Some(gpu) => return Ok(gpu),
+            None => println!("Please enter a number between 0 and {}.", gpus.len() - 1),
+        }
+    }
+}
+
+/// Position of `gpu` in the `--index` order, `None` for integrated GPUs.
+fn discrete_index(discrete: &[&GPU], gpu: &GPU) -> Option<usize> {
+    discrete.iter().position(|d| std::ptr::eq(*d, gpu))
+}
+
+/// Prints the GPU list, with `group` under a header per vendor (table format only).
+fn print_gpus(
+    gpus: &[&GPU],
+    discrete: &[&GPU],
+    colors: &config::Colors,
+    format: cli::Format,
+    group: bool,
+) {
+    let rows = gpus.iter().map(|d| {
+        let index = match discrete_index(discrete, d) {
+            Some(index) => index.to_string(),
+            None => String::from("-"),
+        };
+        let fan = match d.fan_speed() {
+            Some(rpm) => format!("{rpm} RPM"),
+            None => String::from("-"),
+        };
+        let card = match d.card_index() {
+            Some(card) => format!("card{card}"),
+            None => String::from("-"),
+        };
+        (index, d, d.pci_slot().unwrap_or_default(), card, fan)
+    });
+    if format == cli::Format::Plain {
+        for (index, d, pci, card, fan) in rows {
+            println!("{index}\t{}\t{pci}\t{card}\t{}\t{fan}", d.name, d.driver);
+        }
+        return;
+    }
+    let mut table = table::Table::new();
+    table.row(
+        ["#", "GPU", "PCI", "CARD", "DRIVER", "FAN"]
+            .iter()
+            .map(|header| header.bold().to_string())
+            .collect(),
+    );
+    for (index, d, pci, card, fan) in rows {
+        table.row(vec![
+            index,
+            d.name_fancy(colors).bold().to_string(),
+            pci,
+            card,
+            d.driver.clone(),
+            fan,
+        ]);
+    }
+    println!("{}", "-- GPUs --".bold());
+    let mut lines = table.lines().into_iter();
+    if let Some(header) = lines.next() {
+        println!("{header}");
+    }
+    let rows: Vec<(&GPU, String)> = gpus.iter().copied().zip(lines).collect();
+    let print_row = |(d, line): &(&GPU, String)| {
+        println!("{line}");
+        if log::level() >= log::Level::Verbose {
+            for (label, value) in d.topology() {
+                println!("    {}: {}", label.bold(), value);
+            }
+        }
+    };
+    if !group {
+        rows.iter().for_each(print_row);
+        return;
+    }
+    // vendors in the order their best GPU ranks, rows keep their order within a group
+    let mut vendors: Vec<&Vendor> = Vec::new();
+    for gpu in gpus {
+        if !vendors.contains(&&gpu.vendor) {
+            vendors.push(&gpu.vendor);
+        }
+    }
+    for vendor in vendors {
+        let members: Vec<&(&GPU, String)> =
+            rows.iter().filter(|(d, _)| d.vendor == *vendor).collect();
+        let title = format!("{vendor} ({})", members.len());
+        println!("{}", title.color(colors.get(vendor)).bold());
+        members.into_iter().for_each(print_row);
+    }
+}
+
+fn set_power(gpu: &GPU, power: cli::Power, colors: &config::Colors) -> Result<(), Error> {
+    match power {
+        cli::Power::Save => {
+            let users = gpu.users();
+            if !users.is_empty() {
+                let pids: Vec<String> = users.iter().map(|pid| pid.to_string()).collect();
+                return Err(Error::Power(format!(
+                    "{} is in use by process {}, not powering it down",
+                    gpu.name,
+                    pids.join(", ")
+                )));
+            }
+            gpu.set_power_control("auto")?;
+            println!(
+                "{} will be suspended while idle (currently {})",
+                gpu.name_fancy(colors),
+                gpu.runtime_status()
+                    .unwrap_or_else(|| String::from("unknown"))
+            );
+        }
+        cli::Power::On => {
+            gpu.set_power_control("on")?;
+            println!("{} is kept powered on", gpu.name_fancy(colors));
+        }
+    }
+    Ok(())
+}
+
+/// A display output of a DRM card, from the `/sys/class/drm/card<n>-<connector>` entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connector {
+    /// The connector's name without the card, e.g. `HDMI-A-1`.
+    pub name: String,
+    /// Whether a display is attached.
+    pub connected: bool,
+    /// PCI address of the GPU whose card the connector belongs to.
+    pub gpu: Option<String>,
+}
+
+/// Every connector in sysfs, for `--output`.
+pub fn connectors() -> Result<Vec<Connector>, Error> {
+    let mut connectors = Vec::new();
+    for entry in std::fs::read_dir(sysfs_root().join("class/drm"))?.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name();
+        let name = match file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix("card"))
+            .and_then(|name| name.split_once('-'))
+        {
+            Some((_, name)) => name.to_string(),
+            None => continue,
+        };
+        let status = std::fs::read_to_string(entry.path().join("status")).unwrap_or_default();
+        // the connector lives under its card, whose `device` link is the GPU itself
+        let gpu = entry
+            .path()
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.parent()?.join("device").canonicalize().ok())
+            .and_then(|device| Some(device.file_name()?.to_str()?.to_string()));
+        connectors.push(Connector {
+            name,
+            connected: status.trim() == "connected",
+            gpu,
+        });
+    }
+    Ok(connectors)
+}
+
+/// The GPU driving `connector` (e.g. `HDMI-A-1`) among `connectors`, if a display is attached.
+fn connector_gpu<'a>(
+    gpus: &'a [GPU],
+    connectors: &[Connector],
+    connector: &str,
+) -> Result<&'a GPU, Error> {
+    let Some(found) = connectors
+        .iter()
+        .find(|c| c.name.eq_ignore_ascii_case(connector))
+    else {
+        let mut known: Vec<&str> = connectors.iter().map(|c| c.name.as_str()).collect();
+        known.sort();
+        return Err(Error::NoMatchingGpu(format!(
+            "No output named {connector} (available: {})",
+            known.join(", ")
+        )));
+    };
+    if !found.connected {
+        return Err(Error::NoMatchingGpu(format!(
+            "Output {} has no display connected",
+            found.name
+        )));
+    }
+    gpus.iter()
+        .find(|gpu| found.gpu.is_some() && gpu.pci_address() == found.gpu.as_deref())
+        .ok_or_else(|| {
+            Error::NoMatchingGpu(format!(
+                "Output {} isn't driven by a detected GPU",
+                found.name
+            ))
+        })
+}
+
+/// Discrete GPUs ordered by PCI slot, which is what `--index` counts into.
+fn discrete_gpus(gpus: &[GPU]) -> Vec<&GPU> {
+    let mut discrete: Vec<&GPU> = gpus.iter().filter(|gpu| !gpu.integrated).collect();
+    discrete.sort_by_key(|gpu| gpu.pci_slot());
+    discrete
+}
+
+/// Leaves only the GPUs `--vendor` and `--device-id` ask for, failing if none are left.
+pub fn retain_requested(gpus: &mut Vec<GPU>, options: &cli::Options) -> Result<(), Error> {
+    if let Some(vendor) = &options.vendor {
+        gpus.retain(|gpu| gpu.vendor == *vendor);
+        if gpus.is_empty() {
+            return Err(Error::NoMatchingGpu(format!("No {vendor} GPU found")));
+        }
+    }
+    if let Some((vendor_id, device_id)) = options.device_id {
+        gpus.retain(|gpu| gpu.pci_ids == Some((vendor_id, device_id)));
+        if gpus.is_empty() {
+            return Err(Error::NoMatchingGpu(format!(
+                "No GPU with PCI ID {vendor_id:04x}:{device_id:04x} found"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// What [`select_gpu`] chose. The cases that need the user or primer's state to settle are
+/// left to [`resolve_selection`].
+#[derive(Debug, Clone)]
+pub enum Selection<'a> {
+    Selected(&'a GPU),
+    /// Discrete GPUs ranked the same, best first. The first in PCI slot order is used unless
+    /// the user picks another.
+    Tied(Vec<&'a GPU>),
+    /// `--round-robin`: the discrete GPUs to take turns on, in `--index` order.
+    RoundRobin(Vec<&'a GPU>),
+}
+
+/// Picks the GPU to launch on from `gpus`, which have to be in ranking order (see [`rank`]):
+/// integrated graphics for `--igpu`, `--output` (looked up in `connectors`), `--card`,
+/// `--index` or `--round-robin` if given, otherwise the best ranked GPU. It only looks at its
+/// arguments, so it prompts for nothing and doesn't touch the round-robin state.
+pub fn select_gpu<'a>(
+    gpus: &'a [GPU],
+    options: &cli::Options,
+    config: &config::Config,
+    connectors: &[Connector],
+) -> Result<Selection<'a>, Error> {
+    if options.igpu {
+        return gpus
+            .iter()
+            .find(|gpu| gpu.integrated)
+            .map(Selection::Selected)
+            .ok_or_else(|| {
+                Error::NoMatchingGpu(String::from(
+                    "No integrated GPU found to run on without offloading",
+                ))
+            });
+    }
+    let discrete = discrete_gpus(gpus);
+    Ok(Selection::Selected(
+        match (&options.output, options.card, options.index) {
+            (Some(connector), _, _) => connector_gpu(gpus, connectors, connector)?,
+            (None, Some(card), _) => gpus
+                .iter()
+                .find(|gpu| gpu.card_index() == Some(card))
+                .ok_or_else(|| Error::NoMatchingGpu(format!("No GPU owns /dev/dri/card{card}")))?,
+            (None, None, Some(index)) => match discrete.get(index) {
+                Some(gpu) => gpu,
+                None => {
+                    let vendor = match &options.vendor {
+                        Some(vendor) => format!("{vendor} "),
+                        None => String::new(),
+                    };
+                    return Err(Error::NoMatchingGpu(format!(
+                        "No discrete {vendor}GPU with index {index} ({} found)",
+                        discrete.len()
+                    )));
+                }
+            },
+            (None, None, None) if options.round_robin => {
+                if discrete.is_empty() {
+                    return Err(Error::NoMatchingGpu(String::from(
+                        "No discrete GPU to take turns on",
+                    )));
+                }
+                return Ok(Selection::RoundRobin(discrete));
+            }
+            (None, None, None) => {
+                let first = match gpus.first() {
+                    Some(gpu) => gpu,
+                    None => return Err(Error::DeviceNotFound),
+                };
+                if options.no_fallback {
+                    check_preferred(config, first)?;
+                }
+                if let (Some((vendor_id, device_id)), 2..) = (options.device_id, gpus.len()) {
+                    return Err(Error::NoMatchingGpu(format!(
+                    "{} GPUs have PCI ID {vendor_id:04x}:{device_id:04x}, pick one with --index",
+                    gpus.len()
+                )));
+                }
+                let tied: Vec<&GPU> = gpus
+                    .iter()
+                    .filter(|_| !first.integrated)
+                    .filter(|gpu| !gpu.integrated && rank(config, gpu) == rank(config, first))
+                    .collect();
+                if tied.len() > 1 {
+                    return Ok(Selection::Tied(tied));
+                }
+                first
+            }
+        },
+    ))
+}
+
+/// Settles a [`Selection`]: tied GPUs are offered to the user in a terminal or with
+/// `--interactive`, and `--round-robin` takes the next GPU and records that it did.
+pub fn resolve_selection<'a>(
+    selection: Selection<'a>,
+    options: &cli::Options,
+    config: &config::Config,
+) -> Result<&'a GPU, Error> {
+    match selection {
+        Selection::Selected(gpu) => Ok(gpu),
+        Selection::Tied(tied) if options.interactive || std::io::stdout().is_terminal() => {
+            pick_gpu(&tied, &config.colors)
+        }
+        Selection::Tied(tied) => Ok(tied[0]),
+        Selection::RoundRobin(discrete) => Ok(discrete[state::next_round_robin(discrete.len())?]),
+    }
+}
+
+/// Applies the options that override `[general]` keys, marking those keys as set on the
+/// command line.
+pub fn apply_options(config: &mut config::Config, options: &cli::Options) {
+    let overrides = [
+        ("vulkan_uuid", options.vulkan_uuid),
+        ("render_fd", options.render_fd),
+        ("isolate", options.isolate),
+        ("kill_on_unplug", options.kill_on_unplug),
+        ("preset", options.preset.is_some()),
+        ("nice", options.nice.is_some()),
+        ("ionice", options.ionice.is_some()),
+        ("wait_for_child", options.no_wait),
+        ("append_env", !options.append_env.is_empty()),
+        ("keep_env", !options.keep_env.is_empty()),
+        ("drop_env", !options.drop_env.is_empty()),
+    ];
+    for (key, _) in overrides.iter().filter(|(_, given)| *given) {
+        config.origins.insert(key, config::Origin::Cli);
+    }
+    config.vulkan_uuid |= options.vulkan_uuid;
+    config.render_fd |= options.render_fd;
+    config.isolate |= options.isolate;
+    config.kill_on_unplug |= options.kill_on_unplug;
+    if options.preset.is_some() {
+        config.preset = options.preset.clone();
+    }
+    config.nice = options.nice.or(config.nice);
+    config.ionice = options.ionice.or(config.ionice);
+    config.wait_for_child &= !options.no_wait;
+    config.append_env.extend(options.append_env.iter().cloned());
+    config.keep_env.extend(options.keep_env.iter().cloned());
+    config.drop_env.extend(options.drop_env.iter().cloned());
+}
+
+/// Runs the command on the selected GPU, returning the exit code primer should exit with.
+/// `config` is the one from `Config::open`, the CLI overrides are applied here.
+pub fn prime_run(mut options: cli::Options, mut config: config::Config) -> Result<i32, Error> {
+    log::set_level(options.log_level());
+    log::set_journal(config.journal);
+    log::debug(format!("{config:?}"));
+    // saved before anything else can fail so the welcome shows once, and before the CLI
+    // overrides below are applied so they don't end up in the file
+    if config.first_use {
+        log::info(format!(
+            "It seems that it's your first time using primer, welcome!\nYou can edit the config at \"{}\", `primer --dump-default-config` lists every key",
+            config::config_path().display()
+        ));
+        config.first_use = false;
+        // the profile's keys would otherwise be written to [general]
+        match &options.config_profile {
+            Some(_) => {
+                let mut base = config::Config::open()?;
+                base.first_use = false;
+                base.save()?;
+            }
+            None => config.save()?,
+        }
+    }
+    apply_options(&mut config, &options);
+    // both are silently kept at primer's own level if the kernel refuses
+    let privileged = unsafe { libc::geteuid() } == 0;
+    if config.nice.is_some_and(|level| level < 0) && !privileged {
+        log::warn("A negative niceness needs root or CAP_SYS_NICE, without it the command keeps primer's niceness");
+    }
+    if matches!(config.ionice, Some(process::IoPriority::Realtime(_))) && !privileged {
+        log::warn("The realtime I/O class needs root or CAP_SYS_ADMIN, without it the command keeps primer's I/O priority");
+    }
+    if !config.wait_for_child && (options.timeout.is_some() || config.kill_on_unplug) {
+        return Err(Error::InvalidArgument(String::from(
+            "--timeout and kill_on_unplug need primer to wait for the command, they can't be used with --no-wait",
+        )));
+    }
+    if options.stats && (options.exec || options.all || !config.wait_for_child) {
+        return Err(Error::InvalidArgument(String::from(
+            "--stats reports on a single command primer waits for, it can't be used with --exec, --all or --no-wait",
+        )));
+    }
+    if config.on_gpu_reset == config::GpuReset::Restart && !config.isolate {
+        // without its own process group only the command itself would be stopped, and its
+        // children would keep running next to the restarted one
+        log::warn("on_gpu_reset = restart needs isolate (or --isolate) to stop the whole command, only logging resets instead");
+        config.on_gpu_reset = config::GpuReset::Log;
+    }
+    if options.all
+        && (options.capture.is_some() || options.user.is_some() || options.cgroup.is_some())
+    {
+        return Err(Error::InvalidArgument(String::from(
+            "--capture, --user and --cgroup only apply to a single command, they can't be used with --all",
+        )));
+    }
+    if options.exec && (options.timeout.is_some() || config.kill_on_unplug || config.isolate) {
+        return Err(Error::InvalidArgument(String::from(
+            "--timeout, --isolate and kill_on_unplug need primer to stay around as the command's parent, they can't be used with --exec",
+        )));
+    }
+    if let Some(command) = &options.status {
+        return Ok(print_status(command));
+    }
+    if options.list_drivers {
+        return print_drivers(&device::Udev);
+    }
+    if let Some(file) = &options.bug_report {
+        let report = report::generate(&config);
+        match file {
+            Some(path) => {
+                std::fs::write(path, report)?;
+                println!("Bug report written to {}", path.display());
+            }
+            None => print!("{report}"),
+        }
+        return Ok(0);
+    }
+    if options
+        .command
+        .first()
+        .is_some_and(|program| desktop::is_desktop_file(program))
+    {
+        let entry = PathBuf::from(options.command.remove(0));
+        options.command = desktop::command(&entry, &options.command)?;
+        log::debug(format!(
+            "Running {} as {:?}",
+            entry.display(),
+            options.command
+        ));
+    }
+    if options.software {
+        return run_software(&mut options, &config);
+    }
+    if let Some(timeout) = options.wait_for_gpu {
+        wait_for_gpu(&options, timeout)?;
+    }
+    let started = Instant::now();
+    if options.strict_vendor {
+        check_recognized(&device::Udev)?;
+    }
+    let mut gpus = match find_gpus(&device::Udev) {
+        Ok(gpus) => gpus,
+        Err(e) => {
+            log::error("No graphics device was found. Please make sure you have the right drivers installed for your system.");
+            return Err(e);
+        }
+    };
+    let enumeration = started.elapsed();
+
+    let started = Instant::now();
+    sort_by_rank(&mut gpus, &config);
+    retain_requested(&mut gpus, &options)?;
+    let battery_saving = config.power_aware && on_battery() == Some(true);
+    if battery_saving {
+        log::debug("Running on battery, preferring integrated graphics (power_aware)");
+        // stable, so the GPUs keep their order otherwise
+        gpus.sort_by_key(|gpu| !gpu.integrated);
+    }
+    let mut selection = started.elapsed();
+    let discrete = discrete_gpus(&gpus);
+    // indices stay those of the full list, so they can still be passed to --index
+    let shown: Vec<&GPU> = gpus
+        .iter()
+        .filter(|gpu| {
+            !options.connected_only || gpu.connectors().iter().any(|(_, connected)| *connected)
+        })
+        .collect();
+    if options.format == cli::Format::Json {
+        let list: Vec<json::Object> = shown
+            .iter()
+            .map(|gpu| gpu.to_json(discrete_index(&discrete, gpu)))
+            .collect();
+        println!("{}", json::Value::to_json(&list));
+        return Ok(0);
+    }
+    if options.list || log::level() > log::Level::Quiet {
+        print_gpus(
+            &shown,
+            &discrete,
+            &config.colors,
+            options.format,
+            options.group,
+        );
+    }
+    if options.info {
+        gpus.iter().for_each(|gpu| gpu.print_info(&config.colors));
+    }
+    if options.list || options.info {
+        return Ok(0);
+    }
+    if let Some(power) = options.power {
+        let targets: Vec<&GPU> = match options.index {
+            Some(index) => discrete.get(index).into_iter().copied().collect(),
+            None => discrete.clone(),
+        };
+        if targets.is_empty() {
+            return Err(Error::NoMatchingGpu(String::from("No discrete GPU found")));
+        }
+        for gpu in targets {
+            set_power(gpu, power, &config.colors)?;
+        }
+        return Ok(0);
+    }
+    if options.all {
+        return run_on_all(&gpus, &options, &config);
+    }
+    let started = Instant::now();
+    let connectors = match options.output {
+        Some(_) => connectors()?,
+        None => Vec::new(),
+    };
+    let gpu = resolve_selection(
+        select_gpu(&gpus, &options, &config, &connectors)?,
+        &options,
+        &config,
+    )?;
+    if options.igpu {
+        skip_offload(&mut config);
+    }
+    if !gpu.integrated && !options.igpu {
+        if let Some(MuxKind::Discrete(address)) = laptop_mux(&gpus) {
+            if gpu.pci_address() == Some(address.as_str()) {
+                log::warn(format!(
+                    "{} already drives the display (the laptop's mux is set to it), so everything renders on it without offloading. Not setting offload variables.",
+                    gpu.name
+                ));
+                skip_offload(&mut config);
+            }
+        }
+    }
+    selection += started.elapsed();
+    if options.explain {
+        let reason = match (&options.output, options.card, options.index) {
+            _ if options.igpu => String::from("integrated graphics were asked for (--igpu)"),
+            (Some(connector), _, _) => format!("it drives {connector} (--output)"),
+            (None, Some(card), _) => format!("it owns /dev/dri/card{card} (--card)"),
+            (None, None, Some(index)) => format!("it is discrete GPU {index} (--index)"),
+            (None, None, None) if options.round_robin => {
+                String::from("it was its turn (--round-robin)")
+            }
+            (None, None, None) if std::ptr::eq(gpu, &gpus[0]) => String::from("it ranks first"),
+            (None, None, None) => String::from("it was picked from the GPUs tied for first"),
+        };
+        explain(&config, &gpus, gpu, battery_saving, &reason);
+    }
+    if let Some(path) = &options.emit_wrapper {
+        gpu.write_wrapper(path, options.force, &options.env, &config)?;
+        println!(
+            "Wrote launch wrapper for {} to {}",
+            gpu.name_fancy(&config.colors),
+            path.display()
+        );
+        return Ok(0);
+    }
+    if gpu.integrated && !options.igpu {
+        let message = if battery_saving {
+            "Running on battery, using integrated graphics."
+        } else {
+            "No discrete GPU detected, using integrated graphics."
+        };
+        match log::level() {
+            log::Level::Verbose => log::info(message),
+            log::Level::Normal if config.integrated_notice => log::info(message),
+            _ => log::debug(message),
+        }
+    }
+    let command_name = options.command_name().unwrap_or_default();
+    log::debug(format!("Launching \"{command_name}\" on {}", gpu.name));
+    if log::level() >= log::Level::Verbose {
+        if let Some(false) = options.command.first().and_then(|p| likely_uses_gpu(p)) {
+            log::warn(format!(
+                "\"{command_name}\" doesn't link any graphics libraries, offload probably won't change anything for it (it could still load them at runtime)."
+            ));
+        }
+    }
+    if log::level() >= log::Level::Verbose {
+        if let Some(warning) = session_warning(gpu) {
+            log::warn(warning);
+        }
+    }
+    let cgroup_dir = options
+        .cgroup
+        .clone()
+        .and_then(|name| enter_cgroup(&name, &mut options.command));
+    if options.warmup {
+        let started = Instant::now();
+        match gpu.wake(std::time::Duration::from_secs(5)) {
+            Ok(true) => log::debug(format!("Woke the GPU in {:.2?}", started.elapsed())),
+            Ok(false) => log::debug("The GPU is already awake"),
+            Err(err) => log::warn(format!("Couldn't wake the GPU: {err}")),
+        }
+    }
+    let user = options.user.as_deref().map(lookup_user).transpose()?;
+    let started = Instant::now();
+    let mut cmd = gpu.prepare_run(&options.command, &options.env, &config)?;
+    // after prepare_run, so a negative niceness is still set with root's permissions
+    if let Some(user) = &user {
+        process::run_as(&mut cmd, user);
+    }
+    if let Some(dir) = &cgroup_dir {
+        cgroup::join(&mut cmd, dir)?;
+    }
+    if let Some(capture) = &options.capture {
+        capture_output(&mut cmd, capture, &command_name)?;
+    }
+    if options.exec {
+        if options.timings {
+            eprintln!("{}", "-- Timings --".bold());
+            eprintln!("enumeration: {enumeration:.2?}");
+            eprintln!("selection: {selection:.2?}");
+        }
+        // same pid after exec, so the record stays valid until the command exits
+        let launch = state::Launch {
+            pid: std::process::id(),
+            command: command_name,
+            gpu: gpu.name.clone(),
+            pci: gpu.pci_slot().unwrap_or_default(),
+        };
+        record_launch(&launch);
+        // only returns if the command couldn't be started
+        return Err(cmd.exec().into());
+    }
+    let mut job = process::Job::spawn(&mut cmd, config.isolate)?;
+    if options.timings {
+        let spawn = started.elapsed();
+        eprintln!("{}", "-- Timings --".bold());
+        eprintln!("enumeration: {enumeration:.2?}");
+        eprintln!("selection: {selection:.2?}");
+        eprintln!("spawn: {spawn:.2?}");
+    }
+    let mut launch = state::Launch {
+        pid: job.id(),
+        command: command_name,
+        gpu: gpu.name.clone(),
+        pci: gpu.pci_slot().unwrap_or_default(),
+    };
+    record_launch(&launch);
+    if !config.wait_for_child {
+        // the record stays behind for --status and is cleaned up once the command exits
+        job.detach();
+        return Ok(0);
+    }
+    let device = config.kill_on_unplug.then_some(gpu.sysfs.as_path());
+    let monitor = match config.on_gpu_reset {
+        config::GpuReset::Ignore => None,
+        _ => reset_monitor(gpu),
+    };
+    let waiting = Instant::now();
+    let exit = loop {
+        // the timeout covers every run of the command together
+        let timeout = options
+            .timeout
+            .map(|timeout| timeout.saturating_sub(waiting.elapsed()));
+        match job.wait(timeout, device, monitor.as_ref()) {
+            Ok(process::Exit::GpuReset) if config.on_gpu_reset == config::GpuReset::Restart => {
+                log::warn(format!(
+                    "{} was reset, restarting the command on it.",
+                    gpu.name
+                ));
+                job.terminate()?;
+                state::forget(launch.pid);
+                drop(job);
+                job = process::Job::spawn(&mut cmd, config.isolate)?;
+                launch.pid = job.id();
+                if let Err(err) = state::record(&launch) {
+                    log::debug(format!("Couldn't record launch for --status: {err}"));
+                }
+            }
+            Ok(process::Exit::GpuReset) => log::warn(format!(
+                "{} was reset, the command may have lost its GPU and fallen back to software rendering.",
+                gpu.name
+            )),
+            exit => break exit,
+        }
+    };
+    state::forget(launch.pid);
+    if let Some(dir) = cgroup_dir {
+        let _ = std::fs::remove_dir(dir);
+    }
+    let code = exit?.code();
+    if options.stats {
+        print_stats(&job, code);
+    }
+    Ok(code)
+}
+
+/// `--stats`: one line of `key=value` pairs on stderr, so it stays out of the command's
+/// output and is easy to pick apart in scripts.
+fn print_stats(job: &process::Job, code: i32) {
+    if let Some(usage) = job.usage() {
+        eprintln!(
+            "primer stats: wall={:.3}s user={:.3}s sys={:.3}s max_rss={}kB exit={code}",
+            usage.wall.as_secs_f64(),
+            usage.user.as_secs_f64(),
+            usage.system.as_secs_f64(),
+            usage.max_rss_kb
+        );
+    }
+}
+
+/// `--wait-for-gpu`: polls until a GPU that `--vendor` and `--device-id` would keep is
+/// present, or any discrete GPU without them, e.g. an eGPU being plugged in.
+fn wait_for_gpu(options: &cli::Options, timeout: Duration) -> Result<(), Error> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+    let wanted = |gpu: &GPU| {
+        let explicit = options.vendor.is_some() || options.device_id.is_some();
+        options
+            .vendor
+            .as_ref()
+            .is_none_or(|vendor| gpu.vendor == *vendor)
+            && options.device_id.is_none_or(|ids| gpu.pci_ids == Some(ids))
+            && (explicit || !gpu.integrated)
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        // no GPUs at all is just as much a reason to keep waiting
+        if let Some(gpu) = find_gpus(&device::Udev)
+            .unwrap_or_default()
+            .into_iter()
+            .find(wanted)
+        {
+            log::debug(format!("{} is present", gpu.name));
+            return Ok(());
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(Error::NoMatchingGpu(format!(
+                "No matching GPU appeared within {}s (--wait-for-gpu)",
+                timeout.as_secs_f64()
+            )));
+        }
+        log::debug(format!(
+            "Waiting for a matching GPU, {:.0}s left",
+            left.as_secs_f64()
+        ));
+        std::thread::sleep(POLL_INTERVAL.min(left));
+    }
+}
+
+/// Watches `gpu` for resets for `on_gpu_reset`, `None` with a warning if udev can't be asked.
+fn reset_monitor(gpu: &GPU) -> Option<device::ResetMonitor> {
+    match device::ResetMonitor::new(&gpu.sysfs) {
+        Ok(monitor) => Some(monitor),
+        Err(err) => {
+            log::warn(format!(
+                "Can't watch {} for resets, on_gpu_reset is ignored: {err}",
+                gpu.name
+            ));
+            None
+        }
+    }
+}
+
+/// Where sysfs is read from: `$PRIMER_SYSFS_ROOT` if set, so that tests can provide a fake
+/// tree, otherwise `/sys`. GPUs themselves are read from the path their [`DeviceSource`]
+/// reports, see [`GPU::sysfs_path`].
+pub fn sysfs_root() -> PathBuf {
+    std::env::var_os("PRIMER_SYSFS_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/sys"))
+}
+
+/// Where other processes are looked up, `$PRIMER_PROC_ROOT` if set, otherwise `/proc`.
+pub fn proc_root() -> PathBuf {
+    std::env::var_os("PRIMER_PROC_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/proc"))
+}
+
+/// Where `program` would be found through `$PATH`.
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Known trouble with offloading to `gpu` from the current session, going by
+/// `XDG_SESSION_TYPE`, for `--verbose`.
+fn session_warning(gpu: &GPU) -> Option<String> {
+    let session = std::env::var("XDG_SESSION_TYPE").unwrap_or_default();
+    let graphical = ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()));
+    if !graphical {
+        return Some(format!(
+            "No graphical session found (XDG_SESSION_TYPE is \"{session}\" and neither DISPLAY nor WAYLAND_DISPLAY is set), windowed programs will have nothing to draw on."
+        ));
+    }
+    if session != "wayland" || gpu.driver != "nvidia" {
+        return None;
+    }
+    // the proprietary driver accelerates XWayland since 470 and Wayland clients (GBM) since 495
+    let version = std::fs::read_to_string(sysfs_root().join("module/nvidia/version")).ok()?;
+    let version = version.trim();
+    match version.split('.').next()?.parse::<u32>().ok()? {
+        ..470 => Some(format!(
+            "NVIDIA driver {version} can't render for Wayland sessions (XWayland acceleration needs 470, Wayland clients 495), offloaded programs will fall back to software rendering or fail."
+        )),
+        470..495 => Some(format!(
+            "NVIDIA driver {version} predates GBM support (495), so on Wayland only X11 programs running through XWayland can be offloaded to it."
+        )),
+        _ => None,
+    }
+}
+
+/// Libraries whose names in an executable suggest it renders something.
+const GRAPHICS_LIBRARIES: &[&str] = &[
+    "libGL.so",
+    "libGLX.so",
+    "libEGL.so",
+    "libGLESv2.so",
+    "libvulkan.so",
+    "libSDL2",
+    "libSDL3",
+    "libglfw",
+    "libgtk",
+    "libQt",
+    "libX11.so",
+    "libwayland-client.so",
+];
+
+/// Guesses from the libraries an ELF executable links against whether it uses the GPU.
+/// `None` when it can't tell, e.g. for scripts, Windows programs or a missing file.
+fn likely_uses_gpu(program: &str) -> Option<bool> {
+    let path = if program.contains('/') {
+        PathBuf::from(program)
+    } else {
+        find_in_path(program)?
+    };
+    // the dynamic string table sits near the start, no need to read a whole game binary
+    let mut head = Vec::new();
+    std::fs::File::open(path)
+        .ok()?
+        .take(8 << 20)
+        .read_to_end(&mut head)
+        .ok()?;
+    if !head.starts_with(b"\x7fELF") {
+        return None;
+    }
+    Some(GRAPHICS_LIBRARIES.iter().any(|lib| {
+        head.windows(lib.len())
+            .any(|window| window == lib.as_bytes())
+    }))
+}
+
+/// Local time as `YYYYmmdd-HHMMSS`, for file names.
+fn timestamp() -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Applies `keep_env` and `drop_env` to what `cmd` inherits. Call it before setting any
+/// variables, primer's own go on top.
+fn filter_inherited_env(cmd: &mut Command, config: &config::Config) {
+    if !config.keep_env.is_empty() {
+        cmd.env_clear();
+        for key in &config.keep_env {
+            if let Some(value) = std::env::var_os(key) {
+                cmd.env(key, value);
+            }
+        }
+    }
+    for key in &config.drop_env {
+        cmd.env_remove(key);
+    }
+}
+
+/// `--software`: runs the command with Mesa's CPU renderers, llvmpipe for GL and lavapipe for
+/// Vulkan, without looking at the GPUs at all. Everything that doesn't need a GPU applies as for
+/// any other launch.
+fn run_software(options: &mut cli::Options, config: &config::Config) -> Result<i32, Error> {
+    if config.render_fd {
+        return Err(Error::InvalidArgument(String::from(
+            "--render-fd passes the command a GPU, it can't be used with --software",
+        )));
+    }
+    if options.command.is_empty() {
+        return Err(Error::EmptyCommand);
+    }
+    if log::level() > log::Level::Quiet {
+        println!("{}", "-- Using software rendering (llvmpipe) --".bold());
+    }
+    let command_name = options.command_name().unwrap_or_default();
+    let cgroup_dir = options
+        .cgroup
+        .clone()
+        .and_then(|name| enter_cgroup(&name, &mut options.command));
+    let user = options.user.as_deref().map(lookup_user).transpose()?;
+    let (program, args) = options.command.split_first().ok_or(Error::EmptyCommand)?;
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    filter_inherited_env(&mut cmd, config);
+    cmd.env("LIBGL_ALWAYS_SOFTWARE", "1")
+        .env("GALLIUM_DRIVER", "llvmpipe");
+    match lavapipe_icd() {
+        // the loader reads VK_DRIVER_FILES since 1.3.207, older ones VK_ICD_FILENAMES
+        Some(icd) => {
+            cmd.env("VK_DRIVER_FILES", &icd)
+                .env("VK_ICD_FILENAMES", &icd);
+        }
+        None => {
+            log::warn("lavapipe isn't installed, Vulkan programs will still use the GPU drivers")
+        }
+    }
+    let preset = match &config.preset {
+        Some(name) => config.preset_env(name)?,
+        None => env::EnvVars::new(),
+    };
+    let mut vars = env::EnvVars::new();
+    for (key, value) in config.env.iter().chain(&preset) {
+        env::warn_if_reserved(key);
+        // the placeholders all describe a GPU, which --software doesn't have
+        match env::expand(value, |_| None) {
+            Ok(value) => env::set(&mut vars, key, value),
+            Err(err) => log::warn(format!("Not setting {key} with --software: {err}")),
+        }
+    }
+    env::prepend_inherited(&mut vars, &config.append_env);
+    cmd.envs(vars);
+    cmd.envs(options.env.iter().cloned());
+    if let Some(level) = config.nice {
+        process::set_nice(&mut cmd, level);
+    }
+    if let Some(priority) = config.ionice {
+        process::set_io_priority(&mut cmd, priority);
+    }
+    if let Some(user) = &user {
+        process::run_as(&mut cmd, user);
+    }
+    if let Some(dir) = &cgroup_dir {
+        cgroup::join(&mut cmd, dir)?;
+    }
+    if let Some(capture) = &options.capture {
+        capture_output(&mut cmd, capture, &command_name)?;
+    }
+    let mut launch = state::Launch {
+        pid: std::process::id(),
+        command: command_name,
+        gpu: String::from("software rendering"),
+        pci: String::new(),
+    };
+    if options.exec {
+        record_launch(&launch);
+        return Err(cmd.exec().into());
+    }
+    let mut job = process::Job::spawn(&mut cmd, config.isolate)?;
+    launch.pid = job.id();
+    record_launch(&launch);
+    if !config.wait_for_child {
+        job.detach();
+        return Ok(0);
+    }
+    let exit = job.wait(options.timeout, None, None);
+    state::forget(launch.pid);
+    if let Some(dir) = cgroup_dir {
+        let _ = std::fs::remove_dir(dir);
+    }
+    let code = exit?.code();
+    if options.stats {
+        print_stats(&job, code);
+    }
+    Ok(code)
+}
+
+/// Puts the command in its own cgroup for `--cgroup`: in a systemd scope by prefixing
+/// `command` with `systemd-run`, or in a cgroup primer creates, whose directory is returned
+/// for the command to join. Without either it's launched as is, with a warning.
+fn enter_cgroup(name: &str, command: &mut Vec<String>) -> Option<PathBuf> {
+    match cgroup::systemd_scope(name) {
+        Some(prefix) => {
+            log::debug(format!("Running in systemd scope: {prefix:?}"));
+            command.splice(0..0, prefix);
+            None
+        }
+        None => match cgroup::create(name) {
+            Ok(dir) => {
+                if let Err(reason) = cgroup::enable_controllers(&dir) {
+                    log::warn(format!(
+                        "The command runs in {}, but memory and CPU limits can't be set on it: {reason}",
+                        dir.display()
+                    ));
+                }
+                Some(dir)
+            }
+            Err(reason) => {
+                log::warn(format!(
+                    "Can't run the command in its own cgroup, no user systemd instance and {reason}. Launching it without one."
+                ));
+                None
+            }
+        },
+    }
+}
+
+/// The user given to `--user`, who the command can only be run as by root or that user.
+fn lookup_user(spec: &str) -> Result<process::User, Error> {
+    let user = process::User::lookup(spec)
+        .map_err(|reason| Error::InvalidArgument(format!("--user: {reason}")))?;
+    if unsafe { libc::geteuid() } != 0 && user.uid != unsafe { libc::getuid() } {
+        return Err(Error::InvalidArgument(format!(
+            "--user: running commands as \"{}\" needs root",
+            user.name
+        )));
+    }
+    Ok(user)
+}
+
+/// Sends the command's output to the file `--capture` asks for, by default a new one in
+/// `~/.config/primer/logs` named after the command.
+fn capture_output(
+    cmd: &mut Command,
+    capture: &cli::Capture,
+    command_name: &str,
+) -> Result<(), Error> {
+    let path = match capture {
+        cli::Capture::File(path) => path.clone(),
+        cli::Capture::Auto => {
+            let dir = config::primer_dir().join("logs");
+            std::fs::create_dir_all(&dir)?;
+            dir.join(format!("{command_name}-{}.log", timestamp()))
+        }
+    };
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    cmd.stdout(file.try_clone()?).stderr(file);
+    if log::level() > log::Level::Quiet {
+        println!("Capturing output to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Records `launch` for `--status` and logs it to the journal.
+fn record_launch(launch: &state::Launch) {
+    if let Err(err) = state::record(launch) {
+        log::debug(format!("Couldn't record launch for --status: {err}"));
+    }
+    log::journal(
+        6,
+        &format!(
+            "Launched {} (pid {}) on {}",
+            launch.command, launch.pid, launch.gpu
+        ),
+    );
+}
+
+/// The ICD manifest of lavapipe, Mesa's Vulkan CPU driver.
+fn lavapipe_icd() -> Option<PathBuf> {
+    [
+        "/usr/share/vulkan/icd.d",
+        "/usr/local/share/vulkan/icd.d",
+        "/etc/vulkan/icd.d",
+    ]
+    .iter()
+    .filter_map(|dir| std::fs::read_dir(dir).ok())
+    .flatten()
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.path())
+    .find(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("lvp_icd") && name.ends_with(".json"))
+    })
+}
+
+/// `--all`: runs the command once on every GPU, at the same time or with `--sequential` one
+/// after another, and reports how each run ended. The exit code is 0 if every run succeeded,
+/// otherwise that of the first run that didn't.
+fn run_on_all(gpus: &[GPU], options: &cli::Options, config: &config::Config) -> Result<i32, Error> {
+    if options.command.is_empty() {
+        return Err(Error::EmptyCommand);
+    }
+    if options.exec {
+        return Err(Error::InvalidArgument(String::from(
+            "--exec replaces primer with a single command, it can't be used with --all",
+        )));
+    }
+    // signals are forwarded to one process group at a time
+    let group = config.isolate && options.sequential;
+    if config.isolate && !group {
+        log::warn("--isolate only applies to --all together with --sequential");
+    }
+    let command_name = options.command_name().unwrap_or_default();
+    let started = Instant::now();
+    let mut results = Vec::new();
+    let mut running = Vec::new();
+    for gpu in gpus {
+        let job = gpu
+            .prepare_run(&options.command, &options.env, config)
+            .and_then(|mut cmd| process::Job::spawn(&mut cmd, group));
+        let mut job = match job {
+            Ok(job) => job,
+            Err(err) => {
+                results.push((gpu, Err(err)));
+                continue;
+            }
+        };
+        let launch = state::Launch {
+            pid: job.id(),
+            command: command_name.clone(),
+            gpu: gpu.name.clone(),
+            pci: gpu.pci_slot().unwrap_or_default(),
+        };
+        record_launch(&launch);
+        if !config.wait_for_child {
+            job.detach();
+            continue;
+        }
+        if options.sequential {
+            let device = config.kill_on_unplug.then_some(gpu.sysfs.as_path());
+            results.push((gpu, job.wait(options.timeout, device, None)));
+            state::forget(launch.pid);
+        } else {
+            running.push((gpu, job));
+        }
+    }
+    for (gpu, mut job) in running {
+        // every run started at about the same time, so they share one deadline
+        let timeout = options
+            .timeout
+            .map(|timeout| timeout.saturating_sub(started.elapsed()));
+        let device = config.kill_on_unplug.then_some(gpu.sysfs.as_path());
+        results.push((gpu, job.wait(timeout, device, None)));
+        state::forget(job.id());
+    }
+    if results.is_empty() {
+        return Ok(0);
+    }
+    println!("{}", "-- Results --".bold());
+    let mut code = 0;
+    for (gpu, result) in results {
+        let (outcome, exit) = match result {
+            Ok(process::Exit::TimedOut) => (String::from("timed out"), process::EXIT_TIMEOUT),
+            Ok(process::Exit::Unplugged) => (String::from("GPU removed"), process::EXIT_UNPLUGGED),
+            Ok(exit) => (format!("exit code {}", exit.code()), exit.code()),
+            Err(err) => (format!("didn't start: {err}"), 1),
+        };
+        println!("{}: {outcome}", gpu.name_fancy(&config.colors).bold());
+        if code == 0 {
+            code = exit;
+        }
+    }
+    Ok(code)
+}
+
+/// Prints which GPU each running instance of `command` was launched on. The exit code is 0
+/// when at least one is running.
+fn print_status(command: &str) -> i32 {
+    let name = cli::command_name(command);
+    let running: Vec<state::Launch> = state::running()
+        .into_iter()
+        .filter(|launch| launch.command == name)
+        .collect();
+    if running.is_empty() {
+        println!("{name} is not running under primer");
+        return 1;
+    }
+    for launch in running {
+        println!(
+            "{} is running on {} ({}), pid {}",
+            launch.command, launch.gpu, launch.pci, launch.pid
+        );
+    }
+    0
+}
+
+/// `primer config <action>`
+pub fn config_command(args: &[String]) -> Result<i32, Error> {
+    match args.first().map(String::as_str) {
+        Some("restore") => {
+            config::Config::restore()?;
+            println!("Restored the config from its backup.");
+            Ok(0)
+        }
+        Some("show") => {
+            // launch options show the config as a launch with them would use it
+            let options = cli::Options::parse(args[1..].to_vec())?;
+            log::set_level(options.log_level());
+            if !options.command.is_empty() || options.format == cli::Format::Plain {
+                return Err(Error::InvalidArgument(String::from(
+                    "config show takes --format table or --format json and launch options",
+                )));
+            }
+            let path = config::config_path();
+            let mut config = config::Config::open_profile(options.config_profile.as_deref())?;
+            apply_options(&mut config, &options);
+            show_config(&config, &path, options.format == cli::Format::Json)?;
+            Ok(0)
+        }
+        Some(action) => Err(Error::InvalidArgument(format!(
+            "unknown config action \"{action}\""
+        ))),
+        None => Err(Error::InvalidArgument(String::from(
+            "config requires an action",
+        ))),
+    }
+}
+
+/// `primer config show`: every `[general]` key as primer will use it, where it was set, and
+/// the `[env]` variables, presets and profiles the file at `path` defines.
+fn show_config(config: &config::Config, path: &Path, json: bool) -> Result<(), Error> {
+    let general = config.effective();
+    let mut presets: Vec<&String> = config.presets.keys().collect();
+    presets.sort();
+    let mut profiles: Vec<&String> = config.profiles.keys().collect();
+    profiles.sort();
+    if json {
+        let general: Vec<json::Object> = general
+            .iter()
+            .map(|(key, value, origin)| {
+                json::Object::new()
+                    .field("key", *key)
+                    .field("value", value)
+                    .field("source", origin.name())
+            })
+            .collect();
+        let env: Vec<json::Object> = config
+            .env
+            .iter()
+            .map(|(key, value)| json::Object::new().field("key", key).field("value", value))
+            .collect();
+        let show = json::Object::new()
+            .field("path", path.to_string_lossy().as_ref())
+            .field("general", general)
+            .field("env", env)
+            .field("presets", presets)
+            .field("profiles", profiles);
+        println!("{}", json::Value::to_json(&show));
+        return Ok(());
+    }
+    println!("{}", format!("-- Config ({}) --", path.display()).bold());
+    let mut table = table::Table::new();
+    table.row(
+        ["KEY", "VALUE", "SOURCE"]
+            .iter()
+            .map(|header| header.bold().to_string())
+            .collect(),
+    );
+    for (key, value, origin) in general {
+        table.row(vec![key.to_string(), value, origin.name()]);
+    }
+    for line in table.lines() {
+        println!("{line}");
+    }
+    if !config.env.is_empty() {
+        println!("{}", "[env]".bold());
+        for (key, value) in &config.env {
+            println!("{key} = {value}");
+        }
+    }
+    if !presets.is_empty() {
+        let presets: Vec<&str> = presets.iter().map(|name| name.as_str()).collect();
+        println!("{}: {}", "presets".bold(), presets.join(", "));
+    }
+    if !profiles.is_empty() {
+        let profiles: Vec<&str> = profiles.iter().map(|name| name.as_str()).collect();
+        println!("{}: {}", "profiles".bold(), profiles.join(", "));
+    }
+    println!("Command line options apply on top of this, for the launch they're given to.");
+    Ok(())
+}
+
+pub mod log {
+    use dialog::DialogBox;
+    use std::os::unix::net::UnixDatagram;
+    use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+    use std::sync::OnceLock;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Level {
+        Quiet,
+        Normal,
+        Verbose,
+    }
+
+    static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+    pub fn set_level(level: Level) {
+        LEVEL.store(level as u8, Ordering::Relaxed);
+    }
+    pub fn level() -> Level {
+        match LEVEL.load(Ordering::Relaxed) {
+            0 => Level::Quiet,
+            1 => Level::Normal,
+            _ => Level::Verbose,
+        }
+    }
+    static JOURNAL: AtomicBool = AtomicBool::new(false);
+
+    /// Also send messages to the systemd journal, see [`journal`].
+    pub fn set_journal(enabled: bool) {
+        JOURNAL.store(enabled, Ordering::Relaxed);
+    }
+    /// Sends `text` to journald's native socket with the syslog priority `priority`
+    /// (3 error, 4 warning, 6 info, 7 debug) as `SYSLOG_IDENTIFIER=primer`. Does nothing unless
+    /// enabled with `set_journal`, and failures are ignored, e.g. when journald isn't running.
+    pub fn journal(priority: u8, text: &str) {
+        if !JOURNAL.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut datagram = format!("PRIORITY={priority}\nSYSLOG_IDENTIFIER=primer\n").into_bytes();
+        if text.contains('\n') {
+            // values with newlines are sent with their length instead of ending at the newline
+            datagram.extend_from_slice(b"MESSAGE\n");
+            datagram.extend_from_slice(&(text.len() as u64).to_le_bytes());
+        } else {
+            datagram.extend_from_slice(b"MESSAGE=");
+        }
+        datagram.extend_from_slice(text.as_bytes());
+        datagram.push(b'\n');
+        if let Ok(socket) = UnixDatagram::unbound() {
+            let _ = socket.send_to(&datagram, "/run/systemd/journal/socket");
+        }
+    }
+    /// Whether messages can be shown as dialogs: `$DIALOG` picks a backend explicitly, otherwise
+    /// a graphical session with zenity or kdialog installed is needed. Checked once, so a missing
+    /// backend doesn't mean a failed spawn for every message.
+    fn dialogs_available() -> bool {
+        static AVAILABLE: OnceLock<bool> = OnceLock::new();
+        *AVAILABLE.get_or_init(|| {
+            let graphical = ["DISPLAY", "WAYLAND_DISPLAY"]
+                .iter()
+                .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()));
+            std::env::var_os("DIALOG").is_some()
+                || (graphical
+                    && ["zenity", "kdialog"]
+                        .iter()
+                        .any(|tool| crate::find_in_path(tool).is_some()))
+        })
+    }
+    /// Shows `msg` in a dialog, if there is a way to. Callers print it as well.
+    pub fn show(msg: impl Into<String>) {
+        if !dialogs_available() {
+            return;
+        }
+        dialog::Message::new(msg.into())
+            .title("Primer")
+            .show()
+            .unwrap_or_else(|_| eprintln!("Failed to open dialog!"))
+    }
+    pub fn info(msg: impl std::fmt::Display) {
+        let text = format!("Primer Info: {msg}");
+        println!("{}", &text);
+        journal(6, &text);
+        show(text);
+    }
+    pub fn error(msg: impl std::fmt::Display) {
+        let text = format!("Primer Error: {msg}");
+        eprintln!("{}", &text);
+        journal(3, &text);
+        show(text)
+    }
+    /// Printed to stderr only, warnings don't interrupt the launch with a dialog.
+    pub fn warn(msg: impl std::fmt::Display) {
+        let text = format!("Primer Warning: {msg}");
+        journal(4, &text);
+        if level() > Level::Quiet {
+            eprintln!("{}", &text);
+        }
+    }
+    /// Only printed with `--verbose`, and never shown as a dialog.
+    pub fn debug(msg: impl std::fmt::Display) {
+        if level() >= Level::Verbose {
+            let text = format!("Primer Debug: {msg}");
+            eprintln!("{}", &text);
+            journal(7, &text);
+        }
+    }
+}
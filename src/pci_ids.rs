@@ -0,0 +1,60 @@
+//! Device names from the `pci.ids` database, for GPUs udev has no `ID_MODEL_FROM_DATABASE`
+//! for, without spawning `lspci`.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Where distributions install `pci.ids`, most common first.
+const PATHS: &[&str] = &[
+    "/usr/share/hwdata/pci.ids",
+    "/usr/share/misc/pci.ids",
+    "/usr/share/pci.ids",
+];
+
+/// The name `pci.ids` gives the device, e.g. `GA106 [GeForce RTX 3060]`. The file is parsed
+/// once, on the first lookup.
+pub fn device_name(vendor: u16, device: u16) -> Option<&'static str> {
+    static NAMES: OnceLock<HashMap<(u16, u16), String>> = OnceLock::new();
+    NAMES
+        .get_or_init(|| {
+            PATHS
+                .iter()
+                .find_map(|path| std::fs::read(path).ok())
+                .map(|contents| parse(&String::from_utf8_lossy(&contents)))
+                .unwrap_or_default()
+        })
+        .get(&(vendor, device))
+        .map(String::as_str)
+}
+
+/// Vendors start a line with their ID, their devices follow indented by one tab, and
+/// subsystems by two. The device classes at the end of the file are skipped.
+fn parse(contents: &str) -> HashMap<(u16, u16), String> {
+    let mut names = HashMap::new();
+    let mut vendor = None;
+    for line in contents.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with("C ") {
+            break;
+        }
+        if let Some(device) = line.strip_prefix('\t') {
+            if device.starts_with('\t') {
+                continue;
+            }
+            if let (Some(vendor), Some((device, name))) = (vendor, id(device)) {
+                names.insert((vendor, device), name.to_string());
+            }
+        } else {
+            vendor = id(line).map(|(vendor, _)| vendor);
+        }
+    }
+    names
+}
+
+/// Splits `10de  NVIDIA Corporation` into the ID and the name.
+fn id(line: &str) -> Option<(u16, &str)> {
+    let (id, name) = line.split_once(' ')?;
+    Some((u16::from_str_radix(id, 16).ok()?, name.trim()))
+}
@@ -0,0 +1,455 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::{device::ResetMonitor, log, Error};
+
+/// Exit code used when primer had to stop the command itself, matching `timeout(1)`.
+pub const EXIT_TIMEOUT: i32 = 124;
+/// Exit code used when the command was stopped because its GPU disappeared (`EX_UNAVAILABLE`).
+pub const EXIT_UNPLUGGED: i32 = 69;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a command gets to exit after SIGTERM before it is sent SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Exit {
+    Status(ExitStatus),
+    TimedOut,
+    /// The GPU was removed while the command ran, see `Job::wait`.
+    Unplugged,
+    /// The GPU was reset while the command ran. The command is still running, so callers
+    /// decide what to do with it.
+    GpuReset,
+}
+
+impl Exit {
+    /// The exit code primer should report for this outcome.
+    pub fn code(&self) -> i32 {
+        match self {
+            Exit::Status(status) => status
+                .code()
+                .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+            Exit::TimedOut => EXIT_TIMEOUT,
+            Exit::Unplugged | Exit::GpuReset => EXIT_UNPLUGGED,
+        }
+    }
+}
+
+/// Process group whose members get the signals primer itself receives, 0 when there is none.
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pgid = FORWARD_TO.load(Ordering::Relaxed);
+    if pgid > 0 {
+        unsafe {
+            libc::kill(-pgid, signal);
+        }
+    }
+}
+
+/// The signals passed on to isolated jobs, which aren't in the terminal's foreground group.
+const FORWARDED: [libc::c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+/// How many isolated jobs are running, and the handlers primer had for `FORWARDED` before the
+/// first of them started. They're put back when the last one is gone, so primer can be
+/// interrupted again between and after jobs.
+static FORWARDING: Mutex<(usize, Vec<libc::sigaction>)> = Mutex::new((0, Vec::new()));
+
+/// Sends the signals primer receives to the process group `pgid` from now on.
+fn start_forwarding(pgid: i32) {
+    let mut forwarding = FORWARDING.lock().unwrap_or_else(PoisonError::into_inner);
+    FORWARD_TO.store(pgid, Ordering::Relaxed);
+    if forwarding.0 == 0 {
+        forwarding.1 = FORWARDED
+            .iter()
+            .map(|&signal| unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward_signal as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(signal, &action, &mut previous);
+                previous
+            })
+            .collect();
+    }
+    forwarding.0 += 1;
+}
+
+/// Undoes `start_forwarding` for `pgid`, restoring primer's own handlers after the last job.
+fn stop_forwarding(pgid: i32) {
+    let mut forwarding = FORWARDING.lock().unwrap_or_else(PoisonError::into_inner);
+    // another job started since keeps receiving them
+    let _ = FORWARD_TO.compare_exchange(pgid, 0, Ordering::Relaxed, Ordering::Relaxed);
+    forwarding.0 -= 1;
+    if forwarding.0 == 0 {
+        for (signal, previous) in FORWARDED.iter().zip(&forwarding.1) {
+            unsafe {
+                libc::sigaction(*signal, previous, std::ptr::null_mut());
+            }
+        }
+        forwarding.1.clear();
+    }
+}
+
+/// Makes the command set its niceness to `level` right before it starts. If the kernel
+/// refuses (negative levels need `CAP_SYS_NICE`), it keeps the niceness it inherited.
+pub fn set_nice(cmd: &mut Command, level: i32) {
+    unsafe {
+        cmd.pre_exec(move || {
+            libc::setpriority(libc::PRIO_PROCESS, 0, level);
+            Ok(())
+        });
+    }
+}
+
+/// I/O scheduling class and level for `ionice`, see `ioprio_set(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Level 0 to 7, lower is served first. Needs `CAP_SYS_ADMIN`.
+    Realtime(u8),
+    /// Level 0 to 7, lower is served first. What processes get by default, at level 4.
+    BestEffort(u8),
+    /// Only served when nothing else wants the disk.
+    Idle,
+}
+
+impl IoPriority {
+    /// Parses `idle`, `best-effort[:level]` or `realtime[:level]`, levels defaulting to 4.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (class, level) = match value.trim().split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (value.trim(), None),
+        };
+        let level = match level {
+            Some(level) => level
+                .parse()
+                .ok()
+                .filter(|level| *level <= 7)
+                .ok_or_else(|| format!("\"{level}\" is not a level from 0 to 7"))?,
+            None => 4,
+        };
+        match class {
+            "realtime" => Ok(IoPriority::Realtime(level)),
+            "best-effort" => Ok(IoPriority::BestEffort(level)),
+            "idle" => Ok(IoPriority::Idle),
+            _ => Err(format!(
+                "\"{value}\" is not idle, best-effort[:level] or realtime[:level]"
+            )),
+        }
+    }
+    /// The inverse of [`IoPriority::parse`].
+    pub fn name(&self) -> String {
+        match self {
+            IoPriority::Realtime(level) => format!("realtime:{level}"),
+            IoPriority::BestEffort(level) => format!("best-effort:{level}"),
+            IoPriority::Idle => String::from("idle"),
+        }
+    }
+    fn value(&self) -> libc::c_int {
+        let (class, level) = match *self {
+            IoPriority::Realtime(level) => (1, level),
+            IoPriority::BestEffort(level) => (2, level),
+            IoPriority::Idle => (3, 0),
+        };
+        (class << 13) | libc::c_int::from(level)
+    }
+}
+
+/// Makes the command set its I/O priority right before it starts. Like [`set_nice`], a
+/// refusal leaves it with the priority it inherited.
+pub fn set_io_priority(cmd: &mut Command, priority: IoPriority) {
+    let value = priority.value();
+    unsafe {
+        cmd.pre_exec(move || {
+            // no libc wrapper for it; 1 is IOPRIO_WHO_PROCESS and 0 the calling process
+            libc::syscall(libc::SYS_ioprio_set, 1, 0, value);
+            Ok(())
+        });
+    }
+}
+
+/// The account `--user` runs the command as, looked up before the fork since the lookups
+/// aren't safe to do between fork and exec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Supplementary groups, like `video` or `render` that can be needed for `/dev/dri`.
+    pub groups: Vec<libc::gid_t>,
+    pub home: PathBuf,
+}
+
+impl User {
+    /// Looks up `name`, or `name:group` to use another primary group.
+    pub fn lookup(spec: &str) -> Result<Self, String> {
+        let (name, group) = match spec.split_once(':') {
+            Some((name, group)) => (name, Some(group)),
+            None => (spec, None),
+        };
+        let c_name = CString::new(name).map_err(|_| format!("\"{name}\" is not a user name"))?;
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let mut buf = vec![0 as libc::c_char; 16 * 1024];
+        unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            );
+        }
+        if found.is_null() {
+            return Err(format!("there is no user \"{name}\""));
+        }
+        let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+        let home = PathBuf::from(OsStr::from_bytes(home.to_bytes()));
+        let gid = match group {
+            Some(group) => {
+                let c_group =
+                    CString::new(group).map_err(|_| format!("\"{group}\" is not a group name"))?;
+                let mut entry: libc::group = unsafe { std::mem::zeroed() };
+                let mut found = std::ptr::null_mut();
+                unsafe {
+                    libc::getgrnam_r(
+                        c_group.as_ptr(),
+                        &mut entry,
+                        buf.as_mut_ptr(),
+                        buf.len(),
+                        &mut found,
+                    );
+                }
+                if found.is_null() {
+                    return Err(format!("there is no group \"{group}\""));
+                }
+                entry.gr_gid
+            }
+            None => passwd.pw_gid,
+        };
+        let mut groups = vec![0 as libc::gid_t; 256];
+        let mut count = groups.len() as libc::c_int;
+        if unsafe { libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) }
+            == -1
+        {
+            return Err(format!("\"{name}\" is in too many groups"));
+        }
+        groups.truncate(count as usize);
+        Ok(User {
+            name: name.to_string(),
+            uid: passwd.pw_uid,
+            gid,
+            groups,
+            home,
+        })
+    }
+}
+
+/// Makes the command switch to `user` right before it starts, and points `HOME`, `USER` and
+/// `LOGNAME` at that user. Fails the spawn if the switch isn't allowed.
+pub fn run_as(cmd: &mut Command, user: &User) {
+    cmd.env("HOME", &user.home)
+        .env("USER", &user.name)
+        .env("LOGNAME", &user.name);
+    let (uid, gid, groups) = (user.uid, user.gid, user.groups.clone());
+    unsafe {
+        cmd.pre_exec(move || {
+            // groups and gid first, they can't be changed any more once the uid is dropped
+            if libc::setgroups(groups.len(), groups.as_ptr()) == -1
+                || libc::setgid(gid) == -1
+                || libc::setuid(uid) == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// A launched command. With `group` set it runs in its own process group, so the whole tree
+/// it spawns can be signalled at once, and anything left in that group is terminated when
+/// the job is dropped.
+#[derive(Debug)]
+pub struct Job {
+    child: Child,
+    group: bool,
+    /// Whether primer's signals are forwarded to the group, see `start_forwarding`.
+    forwarding: bool,
+    /// Set once `terminate` has signalled the group, so dropping the job doesn't again.
+    terminated: bool,
+    started: Instant,
+    /// Set once the command was reaped, see `Job::reap`.
+    status: Option<ExitStatus>,
+    usage: Option<Usage>,
+}
+
+/// What a command used over its run, for `--stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub wall: Duration,
+    pub user: Duration,
+    pub system: Duration,
+    /// Peak resident set size in KiB.
+    pub max_rss_kb: u64,
+}
+
+impl Job {
+    pub fn spawn(cmd: &mut Command, group: bool) -> Result<Self, Error> {
+        if group {
+            cmd.process_group(0);
+        }
+        let child = cmd.spawn()?;
+        if group {
+            // the child is no longer in the terminal's foreground group, so pass on ^C and friends
+            start_forwarding(child.id() as i32);
+        }
+        Ok(Self {
+            child,
+            group,
+            forwarding: group,
+            terminated: false,
+            started: Instant::now(),
+            status: None,
+            usage: None,
+        })
+    }
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+    /// Waits for the command, stopping it once `timeout` has passed or, with `device` set, as
+    /// soon as that sysfs path disappears because the GPU was unplugged. With `reset` set it
+    /// returns early with `Exit::GpuReset` when the monitor sees the GPU being reset.
+    pub fn wait(
+        &mut self,
+        timeout: Option<Duration>,
+        device: Option<&Path>,
+        reset: Option<&ResetMonitor>,
+    ) -> Result<Exit, Error> {
+        if timeout.is_none() && device.is_none() && reset.is_none() {
+            let status = self.reap(true)?.expect("a blocking wait returns a status");
+            return Ok(Exit::Status(status));
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(status) = self.reap(false)? {
+                return Ok(Exit::Status(status));
+            }
+            if let (Some(timeout), Some(deadline)) = (timeout, deadline) {
+                if Instant::now() >= deadline {
+                    // a dialog would wait for someone to click it, and timeouts are for runs
+                    // nobody is watching
+                    log::warn(format!(
+                        "Command timed out after {}s, stopping it.",
+                        timeout.as_secs_f64()
+                    ));
+                    self.terminate()?;
+                    return Ok(Exit::TimedOut);
+                }
+            }
+            if device.is_some_and(|device| !device.exists()) {
+                log::error("The GPU the command was running on was removed, stopping it.");
+                self.terminate()?;
+                return Ok(Exit::Unplugged);
+            }
+            if reset.is_some_and(|monitor| monitor.reset()) {
+                return Ok(Exit::GpuReset);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+    /// Leaves the command running on its own: signals are no longer forwarded to it and
+    /// dropping the job doesn't terminate its group.
+    pub fn detach(mut self) {
+        self.stop_forwarding();
+        self.group = false;
+    }
+    fn stop_forwarding(&mut self) {
+        if std::mem::take(&mut self.forwarding) {
+            stop_forwarding(self.child.id() as i32);
+        }
+    }
+    /// Sends SIGTERM, escalating to SIGKILL if the command doesn't exit within the grace period.
+    pub fn terminate(&mut self) -> Result<ExitStatus, Error> {
+        self.signal(libc::SIGTERM);
+        self.terminated = true;
+        if let Some(status) = self.wait_until(Instant::now() + KILL_GRACE)? {
+            return Ok(status);
+        }
+        self.signal(libc::SIGKILL);
+        Ok(self.reap(true)?.expect("a blocking wait returns a status"))
+    }
+    fn wait_until(&mut self, deadline: Instant) -> Result<Option<ExitStatus>, Error> {
+        loop {
+            if let Some(status) = self.reap(false)? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+    /// Resource usage of the command, once it has exited.
+    pub fn usage(&self) -> Option<Usage> {
+        self.usage
+    }
+    /// Reaps the command with `wait4` rather than `Child::wait`, so its resource usage isn't
+    /// lost. Without `block` it returns `None` while the command still runs.
+    fn reap(&mut self, block: bool) -> Result<Option<ExitStatus>, Error> {
+        if let Some(status) = self.status {
+            return Ok(Some(status));
+        }
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        let flags = if block { 0 } else { libc::WNOHANG };
+        loop {
+            let pid = self.child.id() as libc::pid_t;
+            match unsafe { libc::wait4(pid, &mut status, flags, &mut usage) } {
+                0 => return Ok(None),
+                -1 => {
+                    let err = std::io::Error::last_os_error();
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(err.into());
+                    }
+                }
+                _ => break,
+            }
+        }
+        let time = |tv: libc::timeval| {
+            Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+        };
+        let status = ExitStatus::from_raw(status);
+        self.status = Some(status);
+        self.usage = Some(Usage {
+            wall: self.started.elapsed(),
+            user: time(usage.ru_utime),
+            system: time(usage.ru_stime),
+            // Linux reports kilobytes
+            max_rss_kb: usage.ru_maxrss as u64,
+        });
+        Ok(Some(status))
+    }
+    fn signal(&self, signal: libc::c_int) {
+        let pid = self.child.id() as libc::pid_t;
+        unsafe {
+            libc::kill(if self.group { -pid } else { pid }, signal);
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.stop_forwarding();
+        if self.group && !self.terminated {
+            self.signal(libc::SIGTERM);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! `--bug-report`: everything primer knows about the machine in one block of text, so a bug
+//! report doesn't need a round of follow-up questions. Nothing is redacted, the files that went
+//! into it are listed at the end so users can check what they're sharing.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config, device, driver_lines, find_gpus, laptop_mux, on_battery, proc_root, sort_by_rank,
+    sysfs_root, MuxKind,
+};
+
+/// Kernel modules worth mentioning when they're loaded.
+const GPU_MODULES: &[&str] = &[
+    "nvidia",
+    "nvidia_drm",
+    "nvidia_modeset",
+    "nvidia_uvm",
+    "nouveau",
+    "amdgpu",
+    "radeon",
+    "i915",
+    "xe",
+    "vfio_pci",
+    "bbswitch",
+];
+
+#[derive(Default)]
+struct Report {
+    out: String,
+    read: Vec<PathBuf>,
+}
+
+impl Report {
+    fn section(&mut self, title: &str) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out.push_str(&format!("== {title} ==\n"));
+    }
+    fn line(&mut self, line: impl AsRef<str>) {
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+    /// Reads `path`, remembering it for the list of files read if it exists.
+    fn read(&mut self, path: &Path) -> Option<String> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        self.read.push(path.to_path_buf());
+        Some(contents)
+    }
+}
+
+/// The report as text, ending in a newline.
+pub fn generate(config: &config::Config) -> String {
+    let mut report = Report::default();
+    report.section("primer");
+    report.line(format!("version: {}", env!("CARGO_PKG_VERSION")));
+
+    report.section("System");
+    let kernel = report.read(&proc_root().join("sys/kernel/osrelease"));
+    report.line(format!(
+        "kernel: {}",
+        kernel.as_deref().map(str::trim).unwrap_or("unknown")
+    ));
+    for var in [
+        "XDG_SESSION_TYPE",
+        "XDG_CURRENT_DESKTOP",
+        "DISPLAY",
+        "WAYLAND_DISPLAY",
+    ] {
+        let value = std::env::var(var).unwrap_or_default();
+        report.line(format!("{var}: {value}"));
+    }
+    report.line(format!(
+        "on battery: {}",
+        match on_battery() {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        }
+    ));
+
+    let path = config::config_path();
+    report.section(&format!("Config ({})", path.display()));
+    match report.read(&path) {
+        Some(contents) => report.out.push_str(&contents),
+        None => report.line("not found, defaults are used"),
+    }
+
+    report.section("GPUs");
+    match find_gpus(&device::Udev) {
+        Ok(mut gpus) => {
+            sort_by_rank(&mut gpus, config);
+            report.line("in the order primer prefers them");
+            for gpu in &gpus {
+                report.line(format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    gpu.name,
+                    gpu.vendor,
+                    gpu.pci_slot().unwrap_or_default(),
+                    gpu.driver,
+                    if gpu.integrated {
+                        "integrated"
+                    } else {
+                        "discrete"
+                    },
+                    gpu.runtime_status().unwrap_or_default()
+                ));
+            }
+            report.line(format!(
+                "panel: {}",
+                match laptop_mux(&gpus) {
+                    Some(MuxKind::Muxless) => String::from("on integrated graphics, no mux found"),
+                    Some(MuxKind::Integrated) => String::from("mux set to integrated graphics"),
+                    Some(MuxKind::Discrete(address)) => format!("driven by {address}"),
+                    None => String::from("none found"),
+                }
+            ));
+            for gpu in &gpus {
+                report.out.push('\n');
+                report.line(format!("-- {} --", gpu.name));
+                for (label, value) in gpu.info() {
+                    report.line(format!("{label}: {value}"));
+                }
+                for (connector, connected) in gpu.connectors() {
+                    let status = if connected {
+                        "connected"
+                    } else {
+                        "disconnected"
+                    };
+                    report.line(format!("connector {connector}: {status}"));
+                }
+            }
+        }
+        Err(err) => report.line(format!("none found: {err}")),
+    }
+
+    report.section("Drivers");
+    match driver_lines(&device::Udev) {
+        Ok(lines) => lines.iter().for_each(|line| report.line(line)),
+        Err(err) => report.line(format!("couldn't scan devices: {err}")),
+    }
+
+    report.section("Kernel modules");
+    let modules = report
+        .read(&proc_root().join("modules"))
+        .unwrap_or_default();
+    let loaded: Vec<&str> = modules
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|module| GPU_MODULES.contains(module))
+        .collect();
+    if loaded.is_empty() {
+        report.line("no GPU modules loaded");
+    }
+    for module in loaded {
+        let version = report.read(&sysfs_root().join("module").join(module).join("version"));
+        match version {
+            Some(version) => report.line(format!("{module} {}", version.trim())),
+            None => report.line(module),
+        }
+    }
+    if let Some(version) = report.read(&proc_root().join("driver/nvidia/version")) {
+        report.out.push_str(&version);
+    }
+
+    report.section("Files read");
+    report.line("the udev database and the GPUs' sysfs directories");
+    let read = std::mem::take(&mut report.read);
+    for path in read {
+        report.line(path.display().to_string());
+    }
+    report.out
+}
@@ -0,0 +1,101 @@
+//! Runtime records of the commands primer launched, for `--status`.
+
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use tini::Ini;
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Launch {
+    pub pid: u32,
+    /// The command name as returned by `cli::command_name`.
+    pub command: String,
+    pub gpu: String,
+    pub pci: String,
+}
+
+/// `$XDG_RUNTIME_DIR/primer`, or a per-user directory in `/tmp` without one.
+pub fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("primer"),
+        None => std::env::temp_dir().join(format!("primer-{}", unsafe { libc::getuid() })),
+    }
+}
+
+fn launch_path(pid: u32) -> PathBuf {
+    runtime_dir().join(format!("{pid}.ini"))
+}
+
+pub fn record(launch: &Launch) -> Result<(), Error> {
+    std::fs::create_dir_all(runtime_dir())?;
+    Ini::new()
+        .section("launch")
+        .item("pid", launch.pid)
+        .item("command", &launch.command)
+        .item("gpu", &launch.gpu)
+        .item("pci", &launch.pci)
+        .to_file(&launch_path(launch.pid))?;
+    Ok(())
+}
+
+pub fn forget(pid: u32) {
+    let _ = std::fs::remove_file(launch_path(pid));
+}
+
+/// Launches whose process is still alive. Records of processes that are gone are removed.
+pub fn running() -> Vec<Launch> {
+    let entries = match std::fs::read_dir(runtime_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut launches: Vec<Launch> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let ini = Ini::from_file(&entry.path()).ok()?;
+            Some(Launch {
+                pid: ini.get("launch", "pid")?,
+                command: ini.get("launch", "command")?,
+                gpu: ini.get("launch", "gpu").unwrap_or_default(),
+                pci: ini.get("launch", "pci").unwrap_or_default(),
+            })
+        })
+        .filter(|launch| {
+            let alive = crate::proc_root().join(launch.pid.to_string()).exists();
+            if !alive {
+                forget(launch.pid);
+            }
+            alive
+        })
+        .collect();
+    launches.sort_by_key(|launch| launch.pid);
+    launches
+}
+
+/// The index `--round-robin` should use out of `count` GPUs: the one after the index used
+/// last time, wrapping around. The file is locked while it's updated, so launches started in
+/// parallel each get their own turn.
+pub fn next_round_robin(count: usize) -> Result<usize, Error> {
+    std::fs::create_dir_all(runtime_dir())?;
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(runtime_dir().join("round-robin"))?;
+    // the lock goes away with the file when it's closed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut last = String::new();
+    file.read_to_string(&mut last)?;
+    let next = match last.trim().parse::<usize>() {
+        Ok(last) => (last + 1) % count,
+        Err(_) => 0,
+    };
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(next.to_string().as_bytes())?;
+    Ok(next)
+}
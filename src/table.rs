@@ -0,0 +1,96 @@
+//! Column alignment for terminal output that may contain colors.
+
+/// How many columns `s` takes up in a terminal, not counting ANSI escape sequences.
+pub fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences like `\x1b[1;32m` end at the first byte in `@`..=`~`
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Rows of cells, printed with each column as wide as its widest cell.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+    /// The aligned lines, without trailing padding.
+    pub fn lines(&self) -> Vec<String> {
+        let mut widths: Vec<usize> = Vec::new();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate() {
+                let width = visible_width(cell);
+                match widths.get_mut(i) {
+                    Some(max) => *max = (*max).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for (i, cell) in row.iter().enumerate() {
+                    if i > 0 {
+                        line.push_str("  ");
+                    }
+                    line.push_str(cell);
+                    if i + 1 < row.len() {
+                        line.push_str(&" ".repeat(widths[i] - visible_width(cell)));
+                    }
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_sequences_take_no_width() {
+        assert_eq!(visible_width("\x1b[1;32mGeForce\x1b[0m"), 7);
+        assert_eq!(visible_width("plain"), 5);
+    }
+
+    #[test]
+    fn colored_cells_stay_aligned() {
+        let mut table = Table::new();
+        table.row(vec![
+            String::from("0"),
+            String::from("\x1b[32mGeForce RTX 3060\x1b[0m"),
+            String::from("0000_01_00_0"),
+        ]);
+        table.row(vec![
+            String::from("-"),
+            String::from("\x1b[34mIris Xe\x1b[0m"),
+            String::from("0000_00_02_0"),
+        ]);
+        let lines = table.lines();
+        let column = |line: &str| visible_width(&line[..line.find("0000_").unwrap()]);
+        assert_eq!(column(&lines[0]), column(&lines[1]));
+        assert_eq!(column(&lines[0]), "0  GeForce RTX 3060  ".len());
+    }
+}
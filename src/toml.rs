@@ -0,0 +1,281 @@
+//! The subset of TOML primer's config needs: `[table]` headers and `key = value` lines whose
+//! values are strings, booleans, numbers or single-line arrays of those.
+
+/// Tables in file order, each with its keys in file order. Values are kept as plain strings,
+/// arrays joined with `, ` the way the INI config writes lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Document {
+    tables: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl Document {
+    pub fn get(&self, table: &str, key: &str) -> Option<&str> {
+        self.table(table)
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn table_names(&self) -> impl Iterator<Item = &str> {
+        self.tables.iter().map(|(name, _)| name.as_str())
+    }
+    pub fn table(&self, name: &str) -> &[(String, String)] {
+        self.tables
+            .iter()
+            .find(|(table, _)| table == name)
+            .map(|(_, items)| items.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// Parses `input`, with errors of the form `line 3: reason`.
+pub fn parse(input: &str) -> Result<Document, String> {
+    let mut doc = Document::default();
+    let mut table = String::new();
+    for (i, line) in input.lines().enumerate() {
+        let fail = |reason: String| format!("line {}: {reason}", i + 1);
+        let mut rest = line.trim_start();
+        if rest.is_empty() || rest.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = rest.strip_prefix('[') {
+            let (name, after) = table_name(header).map_err(fail)?;
+            expect_end(after).map_err(fail)?;
+            table = name;
+            if !doc.tables.iter().any(|(name, _)| *name == table) {
+                doc.tables.push((table.clone(), Vec::new()));
+            }
+            continue;
+        }
+        let key = match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => {
+                let (key, after) = string(&rest[1..], q).map_err(fail)?;
+                rest = after;
+                key
+            }
+            _ => {
+                let end = rest.find(['=', ' ', '\t']).unwrap_or(rest.len());
+                let key = &rest[..end];
+                rest = &rest[end..];
+                key.to_string()
+            }
+        };
+        let after = rest
+            .trim_start()
+            .strip_prefix('=')
+            .ok_or_else(|| fail(format!("expected `=` after \"{key}\"")))?;
+        let (value, after) = value(after.trim_start()).map_err(fail)?;
+        expect_end(after).map_err(fail)?;
+        if !doc.tables.iter().any(|(name, _)| *name == table) {
+            doc.tables.push((table.clone(), Vec::new()));
+        }
+        let items = &mut doc
+            .tables
+            .iter_mut()
+            .find(|(name, _)| *name == table)
+            .expect("table was just added")
+            .1;
+        if items.iter().any(|(k, _)| *k == key) {
+            return Err(fail(format!("\"{key}\" is defined twice")));
+        }
+        items.push((key, value));
+    }
+    Ok(doc)
+}
+
+/// Renders a string as a TOML basic string.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            // TOML allows no other control characters in basic strings
+            c if c < ' ' || c == '\u{7f}' => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Renders a key, quoting it unless it's a bare key.
+pub fn key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+/// Parses a table header after its `[`, e.g. `profile."my game"]`, into its dotted name with
+/// the quotes removed, returning it and what follows the `]`.
+fn table_name(input: &str) -> Result<(String, &str), String> {
+    let mut parts = Vec::new();
+    let mut rest = input.trim_start();
+    loop {
+        let part = match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => {
+                let (part, after) = string(&rest[1..], q)?;
+                rest = after;
+                part
+            }
+            _ => {
+                let end = rest.find(['.', ']', ' ', '\t']).unwrap_or(rest.len());
+                let (part, after) = rest.split_at(end);
+                if part.is_empty() {
+                    return Err(String::from("empty name in table header"));
+                }
+                rest = after;
+                part.to_string()
+            }
+        };
+        parts.push(part);
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix('.') {
+            rest = after.trim_start();
+        } else if let Some(after) = rest.strip_prefix(']') {
+            return Ok((parts.join("."), after));
+        } else {
+            return Err(String::from("unterminated table header"));
+        }
+    }
+}
+
+fn expect_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected \"{rest}\""))
+    }
+}
+
+/// Parses one value from the start of `input`, returning it and what follows it.
+fn value(input: &str) -> Result<(String, &str), String> {
+    match input.chars().next() {
+        Some(q @ ('"' | '\'')) => string(&input[1..], q),
+        Some('[') => {
+            let mut items = Vec::new();
+            let mut rest = input[1..].trim_start();
+            loop {
+                if let Some(after) = rest.strip_prefix(']') {
+                    return Ok((items.join(", "), after));
+                }
+                let (item, after) = value(rest)?;
+                items.push(item);
+                rest = after.trim_start();
+                if let Some(after) = rest.strip_prefix(',') {
+                    rest = after.trim_start();
+                } else if !rest.starts_with(']') {
+                    return Err(String::from("expected `,` or `]` in array"));
+                }
+            }
+        }
+        Some(_) => {
+            let end = input
+                .find([',', ']', '#', ' ', '\t'])
+                .unwrap_or(input.len());
+            let (bare, rest) = input.split_at(end);
+            let number = bare.replace('_', "");
+            if bare == "true" || bare == "false" || number.parse::<f64>().is_ok() {
+                Ok((number, rest))
+            } else {
+                Err(format!("\"{bare}\" is not a value, strings need quotes"))
+            }
+        }
+        None => Err(String::from("missing value")),
+    }
+}
+
+/// Parses the rest of a string opened with `quote`. Escapes only apply to `"` strings.
+fn string(input: &str, quote: char) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((value, &input[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('b') => value.push('\u{8}'),
+                Some('f') => value.push('\u{c}'),
+                Some(u @ ('u' | 'U')) => {
+                    let len = if u == 'u' { 4 } else { 8 };
+                    let digits: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                    let c = u32::from_str_radix(&digits, 16)
+                        .ok()
+                        .filter(|_| digits.len() == len)
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \"\\{u}{digits}\""))?;
+                    value.push(c);
+                }
+                Some(c) => return Err(format!("unknown escape \"\\{c}\"")),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(String::from("unterminated string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_values_parse_back() {
+        let value = "C:\\Games\t\"quoted\"\nnext";
+        let doc = parse(&format!("[env]\n{} = {}\n", key("odd key"), quote(value))).unwrap();
+        assert_eq!(doc.get("env", "odd key"), Some(value));
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        let value = "a\rb\u{1}c\u{7f}d\u{1b}[0m";
+        let quoted = quote(value);
+        assert!(!quoted.chars().any(|c| c.is_control()), "{quoted}");
+        let doc = parse(&format!("[env]\nKEY = {quoted}\n")).unwrap();
+        assert_eq!(doc.get("env", "KEY"), Some(value));
+    }
+
+    #[test]
+    fn quoted_table_names_parse_back() {
+        let header = format!("[profile.{}]", key("my game"));
+        assert_eq!(header, "[profile.\"my game\"]");
+        let doc = parse(&format!(
+            "{header}\nnice = 5\n[ preset . 'a.b' ] # comment\n"
+        ))
+        .unwrap();
+        assert_eq!(doc.get("profile.my game", "nice"), Some("5"));
+        assert_eq!(doc.table_names().last(), Some("preset.a.b"));
+        assert!(parse("[profile.\"my game]\n").is_err());
+        assert!(parse("[profile.]\n").is_err());
+    }
+
+    #[test]
+    fn arrays_are_joined_like_ini_lists() {
+        let doc =
+            parse("[general]\ngpu_priority = [\"amd\", 'nvidia'] # comment\nnice = -5\n").unwrap();
+        assert_eq!(doc.get("general", "gpu_priority"), Some("amd, nvidia"));
+        assert_eq!(doc.get("general", "nice"), Some("-5"));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(
+            parse("[general]\nfirst_use = false\nfirst_use = true\n"),
+            Err(String::from("line 3: \"first_use\" is defined twice"))
+        );
+        assert!(parse("[general]\ngpu = nvidia\n").is_err());
+    }
+}
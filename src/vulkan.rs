@@ -0,0 +1,75 @@
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// What the Vulkan driver reports about a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VulkanInfo {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub device_name: String,
+    pub api_version: String,
+}
+
+/// The devices in `vulkaninfo --summary`, `None` when it isn't installed or fails. vulkaninfo
+/// wakes every GPU, so it only runs once, on the first call.
+pub fn summary() -> Option<&'static [VulkanInfo]> {
+    static SUMMARY: OnceLock<Option<Vec<VulkanInfo>>> = OnceLock::new();
+    SUMMARY
+        .get_or_init(|| {
+            let output = Command::new("vulkaninfo").arg("--summary").output().ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            Some(parse_summary(&String::from_utf8_lossy(&output.stdout)))
+        })
+        .as_deref()
+}
+
+/// Parses the per-device `key = value` blocks (`GPU0:`, `GPU1:`, ...) of `vulkaninfo --summary`.
+pub fn parse_summary(output: &str) -> Vec<VulkanInfo> {
+    let mut devices = Vec::new();
+    let mut fields: Vec<(&str, &str)> = Vec::new();
+    let mut flush = |fields: &mut Vec<(&str, &str)>| {
+        let get = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, value)| *value)
+        };
+        let id = |key: &str| {
+            get(key).and_then(|id| u16::from_str_radix(id.trim_start_matches("0x"), 16).ok())
+        };
+        if let (Some(vendor_id), Some(device_id), Some(device_name), Some(api_version)) = (
+            id("vendorID"),
+            id("deviceID"),
+            get("deviceName"),
+            get("apiVersion"),
+        ) {
+            devices.push(VulkanInfo {
+                vendor_id,
+                device_id,
+                device_name: device_name.to_string(),
+                api_version: api_version_number(api_version).to_string(),
+            });
+        }
+        fields.clear();
+    };
+    for line in output.lines() {
+        let line = line.trim();
+        if line.starts_with("GPU") && line.ends_with(':') {
+            flush(&mut fields);
+        } else if let Some((key, value)) = line.split_once('=') {
+            fields.push((key.trim(), value.trim()));
+        }
+    }
+    flush(&mut fields);
+    devices
+}
+
+/// Older vulkaninfo versions print `4206799 (1.3.242)`, newer ones just `1.3.242`.
+fn api_version_number(value: &str) -> &str {
+    match value.split_once('(') {
+        Some((_, rest)) => rest.trim_end_matches(')').trim(),
+        None => value,
+    }
+}
@@ -0,0 +1,182 @@
+//! The `primer` binary end to end, each test with a home directory of its own.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// An empty home directory for `test`, removed first if an earlier run left it behind.
+fn home(test: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("primer-test-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(home.join(".config/primer")).unwrap();
+    home
+}
+
+/// Runs primer in `home`, outside any graphical session so errors don't open dialogs.
+fn primer(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_primer"))
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_RUNTIME_DIR", home)
+        .env_remove("DISPLAY")
+        .env_remove("WAYLAND_DISPLAY")
+        .env_remove("DIALOG")
+        .output()
+        .unwrap()
+}
+
+fn config(home: &Path) -> String {
+    std::fs::read_to_string(home.join(".config/primer/config.ini")).unwrap()
+}
+
+#[test]
+fn first_use_is_cleared_by_a_failing_first_run() {
+    let home = home("first-use");
+    let first = primer(&home, &["/nonexistent/primer-test-command"]);
+    assert!(String::from_utf8_lossy(&first.stdout).contains("first time using primer"));
+    assert!(
+        config(&home).contains("first_use = false"),
+        "{}",
+        config(&home)
+    );
+    let second = primer(&home, &["/nonexistent/primer-test-command"]);
+    assert!(!String::from_utf8_lossy(&second.stdout).contains("first time using primer"));
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn no_command_prints_the_usage() {
+    let home = home("no-command");
+    let output = primer(&home, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with(primer::cli::USAGE));
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn errors_are_readable_and_exit_nonzero() {
+    let home = home("errors");
+    let output = primer(&home, &["--bogus", "true"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Primer Error: unknown option \"--bogus\"\n"),
+        "{stderr}"
+    );
+    let output = primer(
+        &home,
+        &["--wait-for-gpu", "0", "--vendor", "nvidia", "true"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[cfg(not(feature = "daemon"))]
+#[test]
+fn daemon_needs_the_feature() {
+    let home = home("daemon");
+    let output = primer(&home, &["daemon"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--features daemon"), "{stderr}");
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn all_rejects_single_command_options() {
+    let home = home("all");
+    for flag in [
+        &["--capture"][..],
+        &["--user", "root"],
+        &["--cgroup", "game"],
+    ] {
+        let mut args = vec!["--all"];
+        args.extend(flag);
+        args.push("true");
+        let output = primer(&home, &args);
+        assert_eq!(output.status.code(), Some(2), "{flag:?}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("can't be used with --all"));
+    }
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn software_rendering_offloads_nothing() {
+    let home = home("software");
+    std::fs::write(
+        home.join(".config/primer/config.ini"),
+        "[general]\nfirst_use = false\n\n[env]\nPRIMER_TEST = set\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_primer"))
+        .args(["--software", "env"])
+        .env("HOME", &home)
+        .env("XDG_RUNTIME_DIR", &home)
+        .env_remove("DISPLAY")
+        .env_remove("WAYLAND_DISPLAY")
+        .env_remove("DRI_PRIME")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let vars: Vec<&str> = stdout.lines().collect();
+    assert!(vars.contains(&"LIBGL_ALWAYS_SOFTWARE=1"), "{stdout}");
+    assert!(vars.contains(&"PRIMER_TEST=set"), "{stdout}");
+    assert!(
+        !vars.iter().any(|var| var.starts_with("DRI_PRIME=")),
+        "{stdout}"
+    );
+    assert!(!vars
+        .iter()
+        .any(|var| var.starts_with("__GLX_VENDOR_LIBRARY_NAME=")));
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn config_show_says_where_each_key_was_set() {
+    let home = home("config-show");
+    std::fs::write(
+        home.join(".config/primer/config.ini"),
+        "[general]\nfirst_use = false\nisolate = true\n\n[profile.battery]\npower_aware = true\n",
+    )
+    .unwrap();
+    let output = primer(
+        &home,
+        &[
+            "config",
+            "show",
+            "--format",
+            "json",
+            "--config-profile",
+            "battery",
+            "--nice",
+            "5",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for key in [
+        r#"{"key":"isolate","value":"true","source":"file"}"#,
+        r#"{"key":"power_aware","value":"true","source":"profile.battery"}"#,
+        r#"{"key":"nice","value":"5","source":"command line"}"#,
+        r#"{"key":"journal","value":"false","source":"default"}"#,
+    ] {
+        assert!(stdout.contains(key), "{key} in {stdout}");
+    }
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn the_first_run_only_saves_keys_that_were_set() {
+    let home = home("first-save");
+    std::fs::write(
+        home.join(".config/primer/config.ini"),
+        "[general]\nisolate = true\n",
+    )
+    .unwrap();
+    primer(&home, &["/nonexistent/primer-test-command"]);
+    let saved = config(&home);
+    assert!(saved.contains("first_use = false"), "{saved}");
+    assert!(saved.contains("isolate = true"), "{saved}");
+    assert!(!saved.contains("journal"), "{saved}");
+    std::fs::remove_dir_all(&home).unwrap();
+}
@@ -0,0 +1,96 @@
+//! Fake devices for driving primer without real hardware.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use primer::device::DeviceInfo;
+use primer::{cli, config, find_gpus, select_gpu, sort_by_rank, Selection, GPU};
+
+/// A PCI display controller at `slot` with the vendor and device ID in `id`, e.g. `10DE:2484`.
+/// Its sysfs path doesn't exist, so sensors and connectors read as absent.
+pub fn display(slot: &str, id: &str, driver: &str, name: &str) -> DeviceInfo {
+    DeviceInfo {
+        syspath: PathBuf::from("/nonexistent/primer-test").join(slot),
+        driver: Some(driver.to_string()),
+        properties: [
+            ("PCI_CLASS", "30000"),
+            ("PCI_ID", id),
+            ("PCI_SLOT_NAME", slot),
+            ("ID_MODEL_FROM_DATABASE", name),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect(),
+        parent: None,
+    }
+}
+
+pub fn intel_igpu() -> DeviceInfo {
+    display(
+        "0000:00:02.0",
+        "8086:A7A0",
+        "i915",
+        "Raptor Lake-P [Iris Xe Graphics]",
+    )
+}
+
+pub fn nvidia() -> DeviceInfo {
+    display(
+        "0000:01:00.0",
+        "10DE:2560",
+        "nvidia",
+        "GA106M [GeForce RTX 3060 Mobile]",
+    )
+}
+
+pub fn amd() -> DeviceInfo {
+    display(
+        "0000:03:00.0",
+        "1002:73DF",
+        "amdgpu",
+        "Navi 22 [Radeon RX 6700M]",
+    )
+}
+
+/// A display controller neither its driver nor its PCI vendor ID gives away.
+pub fn unknown() -> DeviceInfo {
+    display("0000:05:00.0", "1A03:2000", "ast", "ASPEED Graphics Family")
+}
+
+/// A device that isn't a display controller, which primer should never pick.
+pub fn network_card() -> DeviceInfo {
+    let mut dev = display("0000:02:00.0", "8086:2723", "iwlwifi", "Wi-Fi 6 AX200");
+    dev.properties[0].1 = String::from("28000");
+    dev
+}
+
+/// The GPUs among `devices`, sorted the way `primer` sorts them before selecting one.
+pub fn ranked(devices: Vec<DeviceInfo>, config: &config::Config) -> Vec<GPU> {
+    let mut gpus = find_gpus(&devices).expect("no GPU found");
+    sort_by_rank(&mut gpus, config);
+    gpus
+}
+
+/// The GPU `select_gpu` settles on by itself, failing the test on an error, a tie or a
+/// round-robin turn.
+pub fn selected<'a>(gpus: &'a [GPU], options: &cli::Options, config: &config::Config) -> &'a GPU {
+    match select_gpu(gpus, options, config, &[]) {
+        Ok(Selection::Selected(gpu)) => gpu,
+        other => panic!("expected a single GPU, got {other:?}"),
+    }
+}
+
+/// A config with nothing but the defaults, which list NVIDIA, then AMD, then Intel.
+pub fn config() -> config::Config {
+    config::Config {
+        first_use: false,
+        ..config::Config::default()
+    }
+}
+
+pub fn value<'a>(vars: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    vars.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
@@ -0,0 +1,207 @@
+//! GPU detection and launch environments on mocked udev devices.
+
+mod common;
+
+use common::*;
+use primer::config::DriPrimeFormat;
+use primer::{cli, find_gpus, Error, Vendor};
+
+#[test]
+fn vendors_are_mapped_by_driver_and_pci_id() {
+    let gpus = find_gpus(&vec![intel_igpu(), nvidia(), amd()]).unwrap();
+    let vendors: Vec<&Vendor> = gpus.iter().map(|gpu| gpu.vendor()).collect();
+    // amdgpu isn't one of the drivers primer knows by name, the PCI vendor ID gives it away
+    assert_eq!(vendors, [&Vendor::Intel, &Vendor::NVIDIA, &Vendor::AMD]);
+    assert_eq!(gpus[1].name(), "GA106M [GeForce RTX 3060 Mobile]");
+    assert_eq!(gpus[1].vendor_id(), Some(0x10de));
+    assert_eq!(gpus[1].device_id(), Some(0x2560));
+}
+
+#[test]
+fn integrated_gpus_are_the_ones_on_the_root_bus() {
+    let gpus = find_gpus(&vec![intel_igpu(), nvidia()]).unwrap();
+    assert!(gpus[0].integrated());
+    assert!(!gpus[1].integrated());
+}
+
+#[test]
+fn devices_other_than_gpus_are_skipped() {
+    let gpus = find_gpus(&vec![network_card(), nvidia()]).unwrap();
+    assert_eq!(gpus.len(), 1);
+    assert_eq!(gpus[0].vendor(), &Vendor::NVIDIA);
+}
+
+#[test]
+fn a_gpu_seen_twice_is_only_kept_once() {
+    let mut again = nvidia();
+    again.syspath.push("drm");
+    let gpus = find_gpus(&vec![nvidia(), again]).unwrap();
+    assert_eq!(gpus.len(), 1);
+}
+
+#[test]
+fn integrated_and_discrete_picks_the_discrete_gpu() {
+    let config = config();
+    let gpus = ranked(vec![intel_igpu(), nvidia()], &config);
+    let gpu = selected(&gpus, &cli::Options::default(), &config);
+    assert_eq!(gpu.vendor(), &Vendor::NVIDIA);
+}
+
+#[test]
+fn two_discrete_gpus_pick_by_gpu_priority() {
+    let mut config = config();
+    let gpus = ranked(vec![amd(), intel_igpu(), nvidia()], &config);
+    let gpu = selected(&gpus, &cli::Options::default(), &config);
+    assert_eq!(gpu.vendor(), &Vendor::NVIDIA);
+
+    config.gpu_priority = primer::config::GpuPriority::Vendors(vec![Vendor::AMD, Vendor::NVIDIA]);
+    let gpus = ranked(vec![amd(), intel_igpu(), nvidia()], &config);
+    let gpu = selected(&gpus, &cli::Options::default(), &config);
+    assert_eq!(gpu.vendor(), &Vendor::AMD);
+}
+
+#[test]
+fn without_a_discrete_gpu_the_integrated_one_is_used() {
+    let config = config();
+    let gpus = ranked(vec![intel_igpu()], &config);
+    let gpu = selected(&gpus, &cli::Options::default(), &config);
+    assert!(gpu.integrated());
+}
+
+#[test]
+fn unknown_vendors_are_not_gpus() {
+    assert!(matches!(
+        find_gpus(&vec![unknown()]),
+        Err(Error::DeviceNotFound)
+    ));
+    let config = config();
+    let gpus = ranked(vec![unknown(), intel_igpu()], &config);
+    assert_eq!(gpus.len(), 1);
+    let gpu = selected(&gpus, &cli::Options::default(), &config);
+    assert_eq!(gpu.vendor(), &Vendor::Intel);
+}
+
+#[test]
+fn nvidia_env_selects_the_proprietary_driver() {
+    let gpus = find_gpus(&vec![nvidia()]).unwrap();
+    let vars = gpus[0].launch_env(&[], &config()).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), Some("pci-0000_01_00_0"));
+    assert_eq!(value(&vars, "__VK_LAYER_NV_optimus"), Some("NVIDIA_only"));
+    assert_eq!(value(&vars, "__GLX_VENDOR_LIBRARY_NAME"), Some("nvidia"));
+    assert_eq!(value(&vars, "PRIMER_GPU_PCI"), Some("0000_01_00_0"));
+}
+
+#[test]
+fn nouveau_env_only_sets_dri_prime() {
+    let dev = display("0000:01:00.0", "10DE:2560", "nouveau", "GA106M");
+    let gpus = find_gpus(&vec![dev]).unwrap();
+    let vars = gpus[0].launch_env(&[], &config()).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), Some("pci-0000_01_00_0"));
+    assert_eq!(value(&vars, "__GLX_VENDOR_LIBRARY_NAME"), None);
+}
+
+#[test]
+fn amd_env_sets_dri_prime() {
+    let gpus = find_gpus(&vec![amd()]).unwrap();
+    let vars = gpus[0].launch_env(&[], &config()).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), Some("pci-0000_03_00_0"));
+    assert_eq!(value(&vars, "__VK_LAYER_NV_optimus"), None);
+}
+
+#[test]
+fn intel_integrated_env_offloads_nothing() {
+    let gpus = find_gpus(&vec![intel_igpu()]).unwrap();
+    let vars = gpus[0].launch_env(&[], &config()).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), None);
+    assert_eq!(
+        value(&vars, "PRIMER_GPU"),
+        Some("Raptor Lake-P [Iris Xe Graphics]")
+    );
+}
+
+#[test]
+fn intel_discrete_is_not_supported() {
+    let arc = display("0000:03:00.0", "8086:56A0", "i915", "DG2 [Arc A770]");
+    let gpus = find_gpus(&vec![arc]).unwrap();
+    assert!(matches!(
+        gpus[0].launch_env(&[], &config()),
+        Err(Error::UnsupportedVendor(Vendor::Intel))
+    ));
+}
+
+#[test]
+fn user_overrides_of_offload_variables_are_applied() {
+    let gpus = find_gpus(&vec![amd()]).unwrap();
+    let env = [(String::from("DRI_PRIME"), String::from("1"))];
+    let vars = gpus[0].launch_env(&env, &config()).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), Some("1"));
+}
+
+#[test]
+fn dri_prime_formats() {
+    let sysfs = std::env::temp_dir().join(format!("primer-test-{}-dri-prime", std::process::id()));
+    std::fs::create_dir_all(sysfs.join("drm/card1")).unwrap();
+    std::fs::create_dir_all(sysfs.join("drm/renderD129")).unwrap();
+    let mut dev = amd();
+    dev.syspath = sysfs.clone();
+    let gpus = find_gpus(&vec![dev]).unwrap();
+    let dri_prime = |format| gpus[0].dri_prime_value(format).unwrap();
+    assert_eq!(dri_prime(DriPrimeFormat::Pci), "pci-0000_03_00_0");
+    assert_eq!(dri_prime(DriPrimeFormat::PciAddress), "pci-0000:03:00.0");
+    assert_eq!(dri_prime(DriPrimeFormat::Index), "1");
+    std::fs::remove_dir_all(&sysfs).unwrap();
+
+    // the env follows dri_prime_format
+    let config = primer::config::Config {
+        dri_prime_format: DriPrimeFormat::PciAddress,
+        ..config()
+    };
+    let vars = gpus[0].launch_env(&[], &config).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), Some("pci-0000:03:00.0"));
+}
+
+#[test]
+fn dri_prime_index_needs_a_card() {
+    let gpus = find_gpus(&vec![amd()]).unwrap();
+    assert!(matches!(
+        gpus[0].dri_prime_value(DriPrimeFormat::Index),
+        Err(Error::InvalidDevice)
+    ));
+}
+
+#[test]
+fn dri_prime_format_names() {
+    for format in [
+        DriPrimeFormat::Pci,
+        DriPrimeFormat::PciAddress,
+        DriPrimeFormat::Index,
+    ] {
+        assert_eq!(DriPrimeFormat::from_name(format.name()), Some(format));
+    }
+    assert_eq!(DriPrimeFormat::from_name("bogus"), None);
+}
+
+#[test]
+fn unknown_drivers_are_recognized_by_pci_vendor_id() {
+    let dev = display("0000:01:00.0", "10DE:2560", "nvidia-open-custom", "GA106M");
+    let gpus = find_gpus(&vec![dev]).unwrap();
+    assert_eq!(gpus[0].vendor(), &Vendor::NVIDIA);
+    assert_eq!(gpus[0].driver(), "nvidia-open-custom");
+    assert_eq!(Vendor::from_pci_vendor_id(0x1002), Some(Vendor::AMD));
+    assert_eq!(Vendor::from_pci_vendor_id(0x1a03), None);
+}
+
+#[test]
+fn strict_vendor_fails_on_unmappable_display_controllers() {
+    assert!(primer::check_recognized(&vec![intel_igpu(), nvidia(), network_card()]).is_ok());
+    match primer::check_recognized(&vec![unknown(), nvidia()]) {
+        Err(Error::UnrecognizedDevices(devices)) => {
+            assert_eq!(devices, ["0000:05:00.0 (1a03:2000, driver \"ast\")"])
+        }
+        other => panic!("expected an error, got {other:?}"),
+    }
+    // a card passed through to a VM can't be used anyway
+    let mut passed_through = unknown();
+    passed_through.driver = Some(String::from("vfio-pci"));
+    assert!(primer::check_recognized(&vec![passed_through, nvidia()]).is_ok());
+}
@@ -0,0 +1,42 @@
+//! Signal handling around isolated jobs. Handlers are process wide, so everything is in one
+//! test.
+
+use std::process::Command;
+
+use primer::process::Job;
+
+/// The handler currently installed for `signal`.
+fn handler(signal: libc::c_int) -> libc::sighandler_t {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(signal, std::ptr::null(), &mut action);
+        action.sa_sigaction
+    }
+}
+
+#[test]
+fn signal_handlers_are_restored_after_isolated_jobs() {
+    let before = [libc::SIGINT, libc::SIGTERM].map(handler);
+    let spawn = || Job::spawn(Command::new("sleep").arg("30"), true).unwrap();
+
+    let mut job = Job::spawn(&mut Command::new("true"), true).unwrap();
+    assert_ne!(handler(libc::SIGINT), before[0]);
+    job.wait(None, None, None).unwrap();
+    drop(job);
+    assert_eq!([libc::SIGINT, libc::SIGTERM].map(handler), before);
+
+    // concurrent jobs, as with --all, restore them once the last one is gone
+    let (mut first, mut second) = (spawn(), spawn());
+    first.terminate().unwrap();
+    drop(first);
+    assert_ne!(handler(libc::SIGINT), before[0]);
+    second.terminate().unwrap();
+    drop(second);
+    assert_eq!(handler(libc::SIGINT), before[0]);
+
+    // and a detached job gives them back right away
+    Job::spawn(&mut Command::new("true"), true)
+        .unwrap()
+        .detach();
+    assert_eq!(handler(libc::SIGINT), before[0]);
+}
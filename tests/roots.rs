@@ -0,0 +1,48 @@
+//! Code that reads `/proc` and `/sys`, pointed at fake trees with `PRIMER_PROC_ROOT` and
+//! `PRIMER_SYSFS_ROOT`. The variables are process wide, so everything is in one test.
+
+use primer::{cgroup, state};
+
+#[test]
+fn fake_proc_and_sysfs_roots() {
+    let root = std::env::temp_dir().join(format!("primer-test-{}-roots", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let (proc, sys) = (root.join("proc"), root.join("sys"));
+    std::fs::create_dir_all(proc.join("self")).unwrap();
+    std::fs::create_dir_all(sys.join("fs/cgroup/user.slice/session.scope")).unwrap();
+    std::fs::write(proc.join("self/cgroup"), "0::/user.slice/session.scope\n").unwrap();
+    std::env::set_var("PRIMER_PROC_ROOT", &proc);
+    std::env::set_var("PRIMER_SYSFS_ROOT", &sys);
+    std::env::set_var("XDG_RUNTIME_DIR", &root);
+
+    let dir = cgroup::create("game").unwrap();
+    assert_eq!(
+        dir.parent(),
+        Some(&*sys.join("fs/cgroup/user.slice/session.scope"))
+    );
+    assert!(dir.is_dir());
+    // a real subtree_control lists what's enabled and takes +controller writes
+    let control = sys.join("fs/cgroup/user.slice/session.scope/cgroup.subtree_control");
+    assert!(cgroup::enable_controllers(&dir).is_err());
+    std::fs::write(&control, "cpu io\n").unwrap();
+    cgroup::enable_controllers(&dir).unwrap();
+    assert_eq!(std::fs::read_to_string(&control).unwrap(), "+memory");
+    std::fs::write(&control, "cpu memory\n").unwrap();
+    cgroup::enable_controllers(&dir).unwrap();
+    assert_eq!(std::fs::read_to_string(&control).unwrap(), "cpu memory\n");
+
+    // launches count as running while their pid has a directory in proc
+    let launch = state::Launch {
+        pid: 4242,
+        command: String::from("vkcube"),
+        gpu: String::from("GA106M"),
+        pci: String::from("0000_01_00_0"),
+    };
+    state::record(&launch).unwrap();
+    std::fs::create_dir(proc.join("4242")).unwrap();
+    assert_eq!(state::running(), [launch]);
+    std::fs::remove_dir(proc.join("4242")).unwrap();
+    assert_eq!(state::running(), []);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
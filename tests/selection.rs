@@ -0,0 +1,266 @@
+//! How `select_gpu` picks among mocked GPUs for the selection options.
+
+mod common;
+
+use common::*;
+use primer::{cli, select_gpu, Connector, Error, Selection, Vendor};
+
+#[test]
+fn no_fallback_fails_without_the_preferred_vendor() {
+    let config = config();
+    let gpus = ranked(vec![intel_igpu(), amd()], &config);
+    let options = cli::Options {
+        no_fallback: true,
+        ..Default::default()
+    };
+    match select_gpu(&gpus, &options, &config, &[]) {
+        Err(Error::NoMatchingGpu(msg)) => assert!(msg.contains("No NVIDIA GPU"), "{msg}"),
+        other => panic!("expected an error, got {other:?}"),
+    }
+    // without the flag the next vendor in gpu_priority is fine
+    let gpu = selected(&gpus, &cli::Options::default(), &config);
+    assert_eq!(gpu.vendor(), &Vendor::AMD);
+}
+
+#[test]
+fn no_fallback_accepts_the_preferred_vendor() {
+    let config = config();
+    let gpus = ranked(vec![intel_igpu(), amd(), nvidia()], &config);
+    let options = cli::Options {
+        no_fallback: true,
+        ..Default::default()
+    };
+    let gpu = selected(&gpus, &options, &config);
+    assert_eq!(gpu.vendor(), &Vendor::NVIDIA);
+}
+
+/// A second NVIDIA card, in a higher slot than [`nvidia`].
+fn second_nvidia() -> primer::device::DeviceInfo {
+    display(
+        "0000:02:00.0",
+        "10DE:2684",
+        "nvidia",
+        "AD102 [GeForce RTX 4090]",
+    )
+}
+
+/// The outputs of the mocked GPUs: eDP-1 on the integrated GPU, HDMI-A-1 on [`nvidia`] with
+/// a display attached, and an empty DP-1 on [`amd`].
+fn outputs() -> Vec<Connector> {
+    [
+        ("eDP-1", true, "0000:00:02.0"),
+        ("HDMI-A-1", true, "0000:01:00.0"),
+        ("DP-1", false, "0000:03:00.0"),
+    ]
+    .into_iter()
+    .map(|(name, connected, gpu)| Connector {
+        name: name.to_string(),
+        connected,
+        gpu: Some(gpu.to_string()),
+    })
+    .collect()
+}
+
+/// What `options` select among `devices` with the default config: the PCI address of the
+/// GPU, `tied: <addresses>` or `round robin: <addresses>`, or the error message.
+fn pick(
+    devices: Vec<primer::device::DeviceInfo>,
+    options: &cli::Options,
+) -> Result<String, String> {
+    let config = config();
+    let mut gpus = ranked(devices, &config);
+    primer::retain_requested(&mut gpus, options).map_err(|err| err.to_string())?;
+    let addresses = |gpus: &[&primer::GPU]| {
+        gpus.iter()
+            .map(|gpu| gpu.pci_address().unwrap_or_default())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    };
+    match select_gpu(&gpus, options, &config, &outputs()).map_err(|err| err.to_string())? {
+        Selection::Selected(gpu) => Ok(gpu.pci_address().unwrap_or_default().to_string()),
+        Selection::Tied(tied) => Ok(format!("tied: {}", addresses(&tied))),
+        Selection::RoundRobin(discrete) => Ok(format!("round robin: {}", addresses(&discrete))),
+    }
+}
+
+#[test]
+fn selection_matrix() {
+    let all = || vec![intel_igpu(), amd(), nvidia(), second_nvidia()];
+    let cases: Vec<(&str, Vec<_>, cli::Options, Result<&str, &str>)> = vec![
+        (
+            "best ranked by default",
+            vec![intel_igpu(), amd(), nvidia()],
+            cli::Options::default(),
+            Ok("0000:01:00.0"),
+        ),
+        (
+            "--vendor overrides gpu_priority",
+            all(),
+            cli::Options {
+                vendor: Some(Vendor::AMD),
+                ..Default::default()
+            },
+            Ok("0000:03:00.0"),
+        ),
+        (
+            "--vendor without such a GPU",
+            vec![intel_igpu(), amd()],
+            cli::Options {
+                vendor: Some(Vendor::NVIDIA),
+                ..Default::default()
+            },
+            Err("No NVIDIA GPU found"),
+        ),
+        (
+            "--device-id matches the PCI ID",
+            all(),
+            cli::Options {
+                device_id: Some((0x10de, 0x2684)),
+                ..Default::default()
+            },
+            Ok("0000:02:00.0"),
+        ),
+        (
+            "--device-id without such a GPU",
+            all(),
+            cli::Options {
+                device_id: Some((0x10de, 0x1111)),
+                ..Default::default()
+            },
+            Err("No GPU with PCI ID 10de:1111 found"),
+        ),
+        (
+            "--index counts discrete GPUs by slot",
+            all(),
+            cli::Options {
+                index: Some(2),
+                ..Default::default()
+            },
+            Ok("0000:03:00.0"),
+        ),
+        (
+            "--index out of range",
+            vec![intel_igpu(), nvidia()],
+            cli::Options {
+                index: Some(1),
+                ..Default::default()
+            },
+            Err("No discrete GPU with index 1 (1 found)"),
+        ),
+        (
+            "--igpu takes integrated graphics",
+            all(),
+            cli::Options {
+                igpu: true,
+                ..Default::default()
+            },
+            Ok("0000:00:02.0"),
+        ),
+        (
+            "--igpu without integrated graphics",
+            vec![amd(), nvidia()],
+            cli::Options {
+                igpu: true,
+                ..Default::default()
+            },
+            Err("No integrated GPU found to run on without offloading"),
+        ),
+        (
+            "--no-fallback refuses integrated graphics",
+            vec![intel_igpu()],
+            cli::Options {
+                no_fallback: true,
+                ..Default::default()
+            },
+            Err(concat!(
+                "No NVIDIA GPU found, not falling back to ",
+                "Raptor Lake-P [Iris Xe Graphics] (--no-fallback)"
+            )),
+        ),
+        (
+            "--output takes the GPU driving the display",
+            all(),
+            cli::Options {
+                output: Some(String::from("hdmi-a-1")),
+                ..Default::default()
+            },
+            Ok("0000:01:00.0"),
+        ),
+        (
+            "--output without a display attached",
+            all(),
+            cli::Options {
+                output: Some(String::from("DP-1")),
+                ..Default::default()
+            },
+            Err("Output DP-1 has no display connected"),
+        ),
+        (
+            "--output with no such connector",
+            all(),
+            cli::Options {
+                output: Some(String::from("DP-9")),
+                ..Default::default()
+            },
+            Err("No output named DP-9 (available: DP-1, HDMI-A-1, eDP-1)"),
+        ),
+        (
+            "--round-robin leaves the turn to the caller",
+            all(),
+            cli::Options {
+                round_robin: true,
+                ..Default::default()
+            },
+            Ok("round robin: 0000:01:00.0, 0000:02:00.0, 0000:03:00.0"),
+        ),
+        (
+            "--card with no such card",
+            all(),
+            cli::Options {
+                card: Some(7),
+                ..Default::default()
+            },
+            Err("No GPU owns /dev/dri/card7"),
+        ),
+    ];
+    for (name, devices, options, expected) in cases {
+        let expected = expected.map(String::from).map_err(String::from);
+        assert_eq!(pick(devices, &options), expected, "{name}");
+    }
+}
+
+#[test]
+fn ties_go_to_the_lower_pci_slot() {
+    let config = config();
+    let gpus = ranked(vec![second_nvidia(), intel_igpu(), nvidia()], &config);
+    assert_eq!(gpus[0].pci_address(), Some("0000:01:00.0"));
+    // resolve_selection uses the first of them unless the user picks another one
+    assert_eq!(
+        pick(
+            vec![second_nvidia(), intel_igpu(), nvidia()],
+            &cli::Options::default()
+        ),
+        Ok(String::from("tied: 0000:01:00.0, 0000:02:00.0"))
+    );
+}
+
+#[test]
+fn igpu_launches_set_no_dri_prime() {
+    // reverse PRIME would otherwise point DRI_PRIME at the integrated GPU
+    let mut config = primer::config::Config {
+        reverse_prime: true,
+        ..config()
+    };
+    let gpus = ranked(vec![intel_igpu(), nvidia()], &config);
+    let options = cli::Options {
+        igpu: true,
+        ..Default::default()
+    };
+    let gpu = selected(&gpus, &options, &config);
+    assert!(gpu.integrated());
+    assert!(value(&gpu.launch_env(&[], &config).unwrap(), "DRI_PRIME").is_some());
+    primer::skip_offload(&mut config);
+    let vars = gpu.launch_env(&[], &config).unwrap();
+    assert_eq!(value(&vars, "DRI_PRIME"), None);
+    assert_eq!(value(&vars, "__GLX_VENDOR_LIBRARY_NAME"), None);
+}